@@ -0,0 +1,172 @@
+//! A procedural macro that generates rust enums and associated traits from C
+//! headers at compile time, so that there are no generated files to keep up
+//! to date.
+//!
+//! ```ignore
+//! #[macro_use]
+//! extern crate rust_enum_derive_macro;
+//!
+//! c_enum!(path = "include/if.h", enum = "net_device_flags",
+//!         derive(Debug, PartialEq), display, fromstr);
+//! ```
+//!
+//! `path` is relative to the directory of the crate's Cargo.toml, and `enum`
+//! picks one enum out of the header by its C name (every enum in it is
+//! generated if not specified). Everything else is the same as the
+//! `[rust-enum-derive]` table of a .toml file: `key = "value"` for strings,
+//! `key = true` or just `key` for flags, and `derive(...)` is short for
+//! `derive = "..."`.
+
+extern crate proc_macro;
+extern crate rust_enum_derive;
+extern crate toml;
+
+use proc_macro::{Delimiter, TokenStream, TokenTree};
+use rust_enum_derive::{Error, FileArgs};
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+/// Generate enums from a C header. See the crate documentation for the
+/// options.
+#[proc_macro]
+pub fn c_enum(input: TokenStream) -> TokenStream {
+    let code = match expand(input) {
+        Ok(code) => code,
+        Err(msg) => format!("compile_error!({:?});", msg),
+    };
+    match code.parse() {
+        Ok(ts) => ts,
+        Err(_) => format!("compile_error!({:?});", "generated code that isn't valid rust")
+                      .parse().unwrap(),
+    }
+}
+
+fn expand(input: TokenStream) -> Result<String, String> {
+    let (path, table) = try!(parse_args(input));
+    let path = match env::var_os("CARGO_MANIFEST_DIR") {
+        Some(dir) => PathBuf::from(dir).join(path),
+        None => path,
+    };
+    let mut s = String::new();
+    if let Err(e) = File::open(&path).and_then(|mut f| f.read_to_string(&mut s)) {
+        return Err(format!("couldn't read {}: {}", path.display(), e))
+    }
+
+    let file_args = try!(FileArgs::from_toml_table(&table).map_err(|e| e.to_string()));
+    let code = match rust_enum_derive::process_str(&s, &file_args) {
+        Ok(code) => code,
+        Err(Error::Parse(mut e)) | Err(Error::Eval(mut e)) => {
+            e.path = Some(path);
+            return Err(e.to_string())
+        }
+        Err(Error::NoInput(_)) => {
+            return Err(format!("couldn't parse any input from {}", path.display()))
+        }
+        Err(e) => return Err(e.to_string()),
+    };
+    // Including the header makes cargo rebuild when it changes.
+    Ok(format!("const _: &'static [u8] = include_bytes!({:?});\n{}",
+               path.to_string_lossy(), code))
+}
+
+// Turn the arguments into the path of the header and a table of options, as
+// if they had come from the [rust-enum-derive] table of a .toml file.
+fn parse_args(input: TokenStream) -> Result<(PathBuf, toml::Table), String> {
+    let mut path = None;
+    let mut table = toml::Table::new();
+    let mut tokens = input.into_iter().peekable();
+    while let Some(tt) = tokens.next() {
+        let key = match tt {
+            TokenTree::Ident(ref i) => i.to_string(),
+            _ => return Err(format!("expected an option, found `{}`", tt)),
+        };
+        let next = tokens.peek().cloned();
+        let value = match next {
+            Some(TokenTree::Punct(ref p)) if p.as_char() == '=' => {
+                tokens.next();
+                match tokens.next() {
+                    Some(TokenTree::Literal(ref l)) => try!(literal_value(&l.to_string(), &key)),
+                    Some(TokenTree::Ident(ref i)) if i.to_string() == "true" => {
+                        toml::Value::Boolean(true)
+                    }
+                    Some(TokenTree::Ident(ref i)) if i.to_string() == "false" => {
+                        toml::Value::Boolean(false)
+                    }
+                    _ => return Err(format!("expected a value for {}", key)),
+                }
+            }
+            Some(TokenTree::Group(ref g)) if g.delimiter() == Delimiter::Parenthesis => {
+                tokens.next();
+                toml::Value::String(g.stream().to_string())
+            }
+            _ => toml::Value::Boolean(true),
+        };
+        match tokens.next() {
+            None => (),
+            Some(TokenTree::Punct(ref p)) if p.as_char() == ',' => (),
+            Some(tt) => return Err(format!("expected `,`, found `{}`", tt)),
+        }
+
+        match &*key {
+            "path" => match value.as_str() {
+                Some(s) => path = Some(PathBuf::from(s)),
+                None => return Err(String::from("path should be a string")),
+            },
+            "enum" => {
+                table.insert(String::from("enums"), toml::Value::Array(vec![value]));
+            }
+            _ => {
+                table.insert(key, value);
+            }
+        }
+    }
+    match path {
+        Some(path) => Ok((path, table)),
+        None => Err(String::from("missing path = \"...\"")),
+    }
+}
+
+// The value of a string or integer literal.
+fn literal_value(s: &str, key: &str) -> Result<toml::Value, String> {
+    if let Ok(i) = s.replace('_', "").parse::<i64>() {
+        return Ok(toml::Value::Integer(i))
+    }
+    match unquote(s) {
+        Some(s) => Ok(toml::Value::String(s)),
+        None => Err(format!("{} should be a string, found `{}`", key, s)),
+    }
+}
+
+// The contents of a string literal, None if s isn't one.
+fn unquote(s: &str) -> Option<String> {
+    if s.starts_with('r') {
+        let hashes = s[1..].chars().take_while(|&c| c == '#').count();
+        let start = 2 + hashes;
+        if s.len() < start + 1 + hashes {
+            return None
+        }
+        return Some(String::from(&s[start..s.len() - 1 - hashes]))
+    }
+    if s.len() < 2 || !s.starts_with('"') || !s.ends_with('"') {
+        return None
+    }
+    let mut out = String::new();
+    let mut chars = s[1..s.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some('0') => out.push('\0'),
+            Some(c) => out.push(c),
+            None => return None,
+        }
+    }
+    Some(out)
+}
@@ -0,0 +1,17 @@
+#[macro_use]
+extern crate rust_enum_derive_macro;
+
+c_enum!(path = "tests/if.h", enum = "net_device_flags",
+        derive(Debug, PartialEq, Clone, Copy), display, fromstr, hex);
+
+mod defines {
+    c_enum!(path = "tests/if.h", define, name = "IfGet", derive(Debug, PartialEq));
+}
+
+#[test]
+fn test_c_enum() {
+    assert_eq!(NetDeviceFlags::IFF_LOOPBACK as i32, 8);
+    assert_eq!(NetDeviceFlags::IFF_DEBUG.to_string(), "IFF_DEBUG");
+    assert_eq!("IFF_UP".parse::<NetDeviceFlags>(), Ok(NetDeviceFlags::IFF_UP));
+    assert_eq!(defines::IfGet::IF_GET_PROTO as i32, 2);
+}
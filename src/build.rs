@@ -0,0 +1,117 @@
+//! Running rust-enum-derive from a build script.
+//!
+//! ```no_run
+//! // build.rs
+//! extern crate rust_enum_derive;
+//!
+//! fn main() {
+//!     rust_enum_derive::build::Builder::new("enums").run().unwrap();
+//! }
+//! ```
+//!
+//! Every `name.toml` under `enums/` is processed along with its `name.in`,
+//! and the generated `name.rs` ends up in `OUT_DIR`, ready to be included:
+//!
+//! ```ignore
+//! include!(concat!(env!("OUT_DIR"), "/name.rs"));
+//! ```
+//!
+//! Cargo is told to run the build script again when any of the inputs, or
+//! the input directory, changes.
+
+use std::env;
+use std::path::PathBuf;
+
+use super::{traverse_dir_impl, Error, Result};
+use expr::SymbolTable;
+
+/// Builds every enum in a directory, the way traverse_dir() does.
+#[derive(Debug)]
+pub struct Builder {
+    input_dir: PathBuf,
+    output_dir: Option<PathBuf>,
+    rerun_if_changed: bool,
+}
+impl Builder {
+    /// A Builder for the .toml and .in files in input_dir. A relative
+    /// input_dir is relative to `CARGO_MANIFEST_DIR`, if that is set.
+    pub fn new<P: Into<PathBuf>>(input_dir: P) -> Builder {
+        let input_dir = input_dir.into();
+        let input_dir = match env::var_os("CARGO_MANIFEST_DIR") {
+            Some(ref dir) if input_dir.is_relative() => PathBuf::from(dir).join(input_dir),
+            _ => input_dir,
+        };
+        Builder { input_dir: input_dir, output_dir: None, rerun_if_changed: true }
+    }
+
+    /// Where to write the generated files (`OUT_DIR` if not specified).
+    pub fn output_dir<P: Into<PathBuf>>(mut self, output_dir: P) -> Builder {
+        self.output_dir = Some(output_dir.into());
+        self
+    }
+
+    /// Whether to print `cargo:rerun-if-changed` for the input directory and
+    /// every file read (true if not specified).
+    pub fn rerun_if_changed(mut self, rerun_if_changed: bool) -> Builder {
+        self.rerun_if_changed = rerun_if_changed;
+        self
+    }
+
+    /// Generate everything, and return the paths of the generated files in
+    /// the order that they were generated.
+    pub fn run(self) -> Result<Vec<PathBuf>> {
+        let output_dir = match self.output_dir {
+            Some(dir) => dir,
+            None => match env::var_os("OUT_DIR") {
+                Some(dir) => PathBuf::from(dir),
+                None => return Err(Error::config(String::from("OUT_DIR isn't set, \
+                                                              use output_dir()"))),
+            },
+        };
+        let rerun_if_changed = self.rerun_if_changed;
+        if rerun_if_changed {
+            // picks up .toml files being added or removed
+            println!("cargo:rerun-if-changed={}", self.input_dir.display());
+        }
+
+        let mut generated = Vec::new();
+        try!(traverse_dir_impl(&self.input_dir, &output_dir, &PathBuf::new(),
+                               &mut SymbolTable::new(),
+                               &mut |toml, input, output| {
+            if rerun_if_changed {
+                println!("cargo:rerun-if-changed={}", toml.display());
+                println!("cargo:rerun-if-changed={}", input.display());
+            }
+            generated.push(output.clone());
+        }));
+        Ok(generated)
+    }
+}
+
+#[test]
+fn test_builder() {
+    use std::fs::{self, File};
+    use std::io::Write;
+
+    let dir = env::temp_dir().join(format!("rust-enum-derive-builder-{}",
+                                           ::std::process::id()));
+    let input_dir = dir.join("in");
+    fs::create_dir_all(input_dir.join("sub")).unwrap();
+    File::create(input_dir.join("a.toml")).unwrap()
+        .write_all(b"[rust-enum-derive]\nname = \"A\"\n").unwrap();
+    File::create(input_dir.join("a.in")).unwrap().write_all(b"A_ONE = 1,\n").unwrap();
+    File::create(input_dir.join("sub/b.toml")).unwrap()
+        .write_all(b"[rust-enum-derive]\nname = \"B\"\n").unwrap();
+    File::create(input_dir.join("sub/b.in")).unwrap()
+        .write_all(b"B_TWO = A_ONE + 1,\n").unwrap();
+
+    let generated = Builder::new(input_dir.clone()).output_dir(dir.join("out"))
+                                                   .rerun_if_changed(false)
+                                                   .run().unwrap();
+    assert_eq!(generated, vec![dir.join("out/a.rs"), dir.join("out/sub/b.rs")]);
+    let mut s = String::new();
+    ::std::io::Read::read_to_string(&mut File::open(&generated[1]).unwrap(), &mut s).unwrap();
+    assert!(s.contains("B_TWO = 2,"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
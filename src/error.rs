@@ -0,0 +1,133 @@
+//! The errors that the library can return.
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// The error type for everything in this crate.
+#[derive(Debug)]
+pub enum Error {
+    /// The configuration is wrong, for example a TOML file without a
+    /// `[rust-enum-derive]` table, an option with the wrong type, or a TOML
+    /// file that can't be parsed at all (the TOML error is the source).
+    Config {
+        /// the configuration file, if the configuration came from one
+        path: Option<PathBuf>,
+        /// what went wrong
+        msg: String,
+        /// the underlying error, if there was one
+        source: Option<Box<StdError + Send + Sync>>,
+    },
+    /// The input couldn't be parsed.
+    Parse(ParseError),
+    /// A value in the input couldn't be evaluated, for example because it
+    /// refers to a constant that hasn't been defined.
+    Eval(ParseError),
+    /// Nothing was found in the input to generate an enum from. Holds the
+    /// name of the input.
+    NoInput(String),
+    /// The input parsed but the code for it can't be generated, for example
+    /// because its values don't fit in any integer type.
+    Generate(String),
+    /// Reading the input or writing the output failed.
+    Io {
+        /// the file or directory that couldn't be read or written, if the
+        /// error came from one
+        path: Option<PathBuf>,
+        /// the I/O error
+        source: io::Error,
+    },
+}
+impl Error {
+    pub(crate) fn config(msg: String) -> Error {
+        Error::Config { path: None, msg: msg, source: None }
+    }
+
+    pub(crate) fn io(source: io::Error, path: &PathBuf) -> Error {
+        Error::Io { path: Some(path.clone()), source: source }
+    }
+
+    // Fill in the path of a configuration error that doesn't have one yet.
+    pub(crate) fn with_path(self, p: &PathBuf) -> Error {
+        match self {
+            Error::Config { path: None, msg, source } => {
+                Error::Config { path: Some(p.clone()), msg: msg, source: source }
+            }
+            e => e,
+        }
+    }
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Config { ref path, ref msg, .. } => match *path {
+                Some(ref p) => write!(f, "{}: {}", p.display(), msg),
+                None => write!(f, "{}", msg),
+            },
+            Error::Parse(ref e) | Error::Eval(ref e) => write!(f, "{}", e),
+            Error::NoInput(ref input) => write!(f, "couldn't parse any input from {}.", input),
+            Error::Generate(ref msg) => write!(f, "{}", msg),
+            Error::Io { ref path, ref source } => match *path {
+                Some(ref p) => write!(f, "{}: {}", p.display(), source),
+                None => write!(f, "{}", source),
+            },
+        }
+    }
+}
+impl StdError for Error {
+    fn source(&self) -> Option<&(StdError + 'static)> {
+        match *self {
+            Error::Config { ref source, .. } => match *source {
+                Some(ref e) => Some(&**e),
+                None => None,
+            },
+            Error::Parse(ref e) | Error::Eval(ref e) => Some(e),
+            Error::NoInput(_) | Error::Generate(_) => None,
+            Error::Io { ref source, .. } => Some(source),
+        }
+    }
+}
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io { path: None, source: e }
+    }
+}
+
+/// A Result whose error is an Error from this crate.
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+/// An error in the input, with enough context to point at it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// the input file (None if the input didn't come from a file)
+    pub path: Option<PathBuf>,
+    /// what the input is called in messages: the path of the file,
+    /// `<stdin>`, `<string>` for process_str() and parse(), or `<input>` for
+    /// process_io()
+    pub input: String,
+    /// line number, starting at 1
+    pub line: usize,
+    /// column (in bytes), starting at 1
+    pub column: usize,
+    /// length (in bytes) of the offending text
+    pub len: usize,
+    /// the whole input line
+    pub text: String,
+    /// what went wrong
+    pub msg: String,
+}
+impl ParseError {
+    /// The offending part of the input line.
+    pub fn snippet(&self) -> &str {
+        let start = ::std::cmp::min(self.column - 1, self.text.len());
+        let end = ::std::cmp::min(start + self.len, self.text.len());
+        &self.text[start..end]
+    }
+}
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.input, self.line, self.column, self.msg)
+    }
+}
+impl StdError for ParseError {}
@@ -0,0 +1,557 @@
+//! A small evaluator for C integer constant expressions, the kind that show
+//! up on the right hand side of enum values and #defines.
+//!
+//! Operator precedence and associativity follow C:
+//!
+//! | precedence | operators              |
+//! |------------|------------------------|
+//! | highest    | `-` `+` `~` `!` (unary)|
+//! |            | `*` `/` `%`            |
+//! |            | `+` `-`                |
+//! |            | `<<` `>>`              |
+//! |            | `<` `<=` `>` `>=`      |
+//! |            | `==` `!=`              |
+//! |            | `&`                    |
+//! |            | `^`                    |
+//! |            | `|`                    |
+//! |            | `&&`                   |
+//! | lowest     | `||`                   |
+//!
+//! Integer literals follow C too: decimal, octal (`0755`), hexadecimal
+//! (`0x1F`) and binary (`0b1010`), with any of the `u`, `l`, `ul`, `ll` and
+//! `ull` suffixes in either case. Literals and results are typed like they
+//! are on an LP64 platform (`int` is 32 bits, `long` and `long long` are 64)
+//! with one difference: signed arithmetic is exact rather than overflowing,
+//! so `1 << 31` is 2147483648. Negating or complementing an `int` that has
+//! only overflowed into its top bit like that is done at 32 bits though, so
+//! `~(1 << 31)` is 0x7FFFFFFF, while `~(1 << 40)` is exact. Unsigned
+//! arithmetic wraps at the width of its type as in C, so `~0U` is 0xFFFFFFFF
+//! and `-1UL` is 0xFFFFFFFFFFFFFFFF.
+//!
+//! Values are evaluated as i128 so that every value of both i64 and u64 can be
+//! represented, the result has to fit in one of them.
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// Constants that have already been defined, by name. Identifiers in an
+/// expression are looked up here.
+pub type SymbolTable = HashMap<String, i128>;
+
+/// An error encountered while evaluating an expression.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalError {
+    /// what went wrong
+    pub msg: String,
+    /// byte offset into the expression where it went wrong
+    pub pos: usize,
+    /// length in bytes of the part of the expression that is wrong
+    pub len: usize,
+}
+impl EvalError {
+    // len is filled in by eval() from whatever token is at pos.
+    fn new(msg: String, pos: usize) -> EvalError {
+        EvalError { msg: msg, pos: pos, len: 0 }
+    }
+}
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
+// The C type of a value. Long stands in for both long and long long.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Type {
+    Int,
+    UInt,
+    Long,
+    ULong,
+}
+impl Type {
+    fn unsigned(&self) -> bool {
+        *self == Type::UInt || *self == Type::ULong
+    }
+
+    fn bits(&self) -> u32 {
+        match *self {
+            Type::Int | Type::UInt => 32,
+            Type::Long | Type::ULong => 64,
+        }
+    }
+
+    // C's usual arithmetic conversions, the type that both operands of a
+    // binary operator are converted to.
+    fn common(a: Type, b: Type) -> Type {
+        if a == b {
+            return a;
+        }
+        if a.unsigned() == b.unsigned() {
+            return if a.bits() >= b.bits() { a } else { b };
+        }
+        let (u, s) = if a.unsigned() { (a, b) } else { (b, a) };
+        if u.bits() >= s.bits() { u } else { s }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Value {
+    n: i128,
+    t: Type,
+}
+impl Value {
+    fn new(n: i128, t: Type) -> Value {
+        Value { n: n, t: t }
+    }
+
+    fn int(n: bool) -> Value {
+        Value::new(n as i128, Type::Int)
+    }
+
+    // Convert to type t. Unsigned types wrap, signed ones are kept exact.
+    fn to(self, t: Type) -> Value {
+        if t.unsigned() {
+            Value::new(self.n.rem_euclid(1 << t.bits()), t)
+        }
+        else {
+            Value::new(self.n, t)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(Value),
+    Ident(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+}
+
+// Longest operators first so that "<<" wins over "<".
+const OPERATORS: [&'static str; 20] = ["<<", ">>", "<=", ">=", "==", "!=", "&&", "||",
+                                       "+", "-", "*", "/", "%", "&", "|", "^", "~", "!",
+                                       "<", ">"];
+
+fn tokenize(s: &str) -> Result<Vec<(Token, usize)>, EvalError> {
+    let bytes = s.as_bytes();
+    let mut v = Vec::new();
+    let mut i = 0;
+
+    'outer: while i < bytes.len() {
+        let c = s[i..].chars().next().unwrap();
+        if !c.is_ascii() {
+            return Err(EvalError::new(format!("unexpected character '{}'", c), i));
+        }
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        if c == '(' {
+            v.push((Token::LParen, i));
+            i += 1;
+            continue;
+        }
+        if c == ')' {
+            v.push((Token::RParen, i));
+            i += 1;
+            continue;
+        }
+        if c.is_digit(10) {
+            let start = i;
+            while i < bytes.len() && bytes[i].is_ascii_alphanumeric() {
+                i += 1;
+            }
+            let n = try!(parse_literal(&s[start..i], start));
+            v.push((Token::Num(n), start));
+            continue;
+        }
+        if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < bytes.len() &&
+                  (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            v.push((Token::Ident(String::from(&s[start..i])), start));
+            continue;
+        }
+        for op in OPERATORS.iter() {
+            if s[i..].starts_with(op) {
+                v.push((Token::Op(op), i));
+                i += op.len();
+                continue 'outer;
+            }
+        }
+        return Err(EvalError::new(format!("unexpected character '{}'", c), i));
+    }
+
+    Ok(v)
+}
+
+fn parse_literal(s: &str, pos: usize) -> Result<Value, EvalError> {
+    let err = || EvalError::new(format!("invalid integer literal '{}'", s), pos);
+
+    let body = s.trim_right_matches(|c| c == 'u' || c == 'U' || c == 'l' || c == 'L');
+    let suffix = s[body.len()..].to_lowercase();
+    let (unsigned, long) = match suffix.as_str() {
+        "" => (false, false),
+        "u" => (true, false),
+        "l" | "ll" => (false, true),
+        "ul" | "lu" | "ull" | "llu" => (true, true),
+        _ => return Err(err()),
+    };
+    if suffix.contains("ll") && !s.contains("ll") && !s.contains("LL") {
+        // lL and Ll aren't valid
+        return Err(err());
+    }
+
+    let (digits, radix) = if body.starts_with("0x") || body.starts_with("0X") {
+        (&body[2..], 16)
+    } else if body.starts_with("0b") || body.starts_with("0B") {
+        (&body[2..], 2)
+    } else if body.len() > 1 && body.starts_with("0") {
+        (&body[1..], 8)
+    } else {
+        (body, 10)
+    };
+    let n = match u64::from_str_radix(digits, radix) {
+        Ok(n) => n as i128,
+        Err(_) => return Err(err()),
+    };
+
+    // The type is the first of these that can hold the value. Decimal
+    // literals without a u suffix are always signed in C, except that they
+    // are allowed to spill over into unsigned long like gcc does.
+    let candidates: &[Type] = match (unsigned, long, radix == 10) {
+        (false, false, true) => &[Type::Int, Type::Long, Type::ULong],
+        (false, false, false) => &[Type::Int, Type::UInt, Type::Long, Type::ULong],
+        (true, false, _) => &[Type::UInt, Type::ULong],
+        (false, true, _) => &[Type::Long, Type::ULong],
+        (true, true, _) => &[Type::ULong],
+    };
+    for t in candidates {
+        let max = if t.unsigned() { (1i128 << t.bits()) - 1 } else { (1i128 << (t.bits() - 1)) - 1 };
+        if n <= max {
+            return Ok(Value::new(n, *t));
+        }
+    }
+    Err(err())
+}
+
+// Binding power of a binary operator, higher binds tighter.
+fn precedence(op: &str) -> Option<u32> {
+    match op {
+        "*" | "/" | "%" => Some(10),
+        "+" | "-" => Some(9),
+        "<<" | ">>" => Some(8),
+        "<" | "<=" | ">" | ">=" => Some(7),
+        "==" | "!=" => Some(6),
+        "&" => Some(5),
+        "^" => Some(4),
+        "|" => Some(3),
+        "&&" => Some(2),
+        "||" => Some(1),
+        _ => None,
+    }
+}
+
+struct Parser<'a> {
+    tokens: &'a [(Token, usize)],
+    symbols: &'a SymbolTable,
+    i: usize,
+    // offset used when reporting errors at the end of the input
+    end: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a (Token, usize)> {
+        self.tokens.get(self.i)
+    }
+
+    fn pos(&self) -> usize {
+        match self.peek() {
+            Some(&(_, pos)) => pos,
+            None => self.end,
+        }
+    }
+
+    // Precedence climbing: parse operands joined by operators that bind at
+    // least as tightly as min_prec. All of C's binary operators are left
+    // associative.
+    fn expr(&mut self, min_prec: u32) -> Result<Value, EvalError> {
+        let mut lhs = try!(self.unary());
+        loop {
+            let (op, pos) = match self.peek() {
+                Some(&(Token::Op(op), pos)) => (op, pos),
+                _ => break,
+            };
+            let prec = match precedence(op) {
+                Some(p) if p >= min_prec => p,
+                _ => break,
+            };
+            self.i += 1;
+            let rhs = try!(self.expr(prec + 1));
+            lhs = try!(apply(op, lhs, rhs, pos));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Value, EvalError> {
+        match self.peek() {
+            Some(&(Token::Op(op), pos)) if op == "-" || op == "+" || op == "~" || op == "!" => {
+                self.i += 1;
+                let v = try!(self.unary());
+                // An int that has overflowed into the top bit, like 1 << 31,
+                // is negated and complemented at 32 bits like it would be in
+                // C. Anything wider stays exact.
+                let wide = v.t == Type::Int && v.n >= 1 << 31 && v.n < 1 << 32;
+                Ok(match op {
+                    "-" if wide => Value::new((v.n as i32).wrapping_neg() as i128, v.t),
+                    "~" if wide => Value::new(!(v.n as i32) as i128, v.t),
+                    "-" => Value::new(try!(checked(v.n.checked_neg(), pos)), v.t).to(v.t),
+                    "~" => Value::new(!v.n, v.t).to(v.t),
+                    "!" => Value::int(v.n == 0),
+                    _ => v,
+                })
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Value, EvalError> {
+        let pos = self.pos();
+        match self.peek() {
+            Some(&(Token::Num(n), _)) => {
+                self.i += 1;
+                Ok(n)
+            }
+            Some(&(Token::LParen, _)) => {
+                self.i += 1;
+                let n = try!(self.expr(0));
+                match self.peek() {
+                    Some(&(Token::RParen, _)) => {
+                        self.i += 1;
+                        Ok(n)
+                    }
+                    _ => Err(EvalError::new(String::from("expected ')'"), self.pos())),
+                }
+            }
+            Some(&(Token::Ident(ref s), _)) => {
+                match self.symbols.get(s) {
+                    Some(n) => {
+                        self.i += 1;
+                        let t = if *n > i64::max_value() as i128 { Type::ULong } else { Type::Long };
+                        Ok(Value::new(*n, t))
+                    }
+                    None => Err(EvalError::new(format!("unknown identifier '{}'", s), pos)),
+                }
+            }
+            Some(&(Token::RParen, _)) => {
+                Err(EvalError::new(String::from("unexpected ')'"), pos))
+            }
+            Some(&(Token::Op(op), _)) => {
+                Err(EvalError::new(format!("unexpected operator '{}'", op), pos))
+            }
+            None => Err(EvalError::new(String::from("unexpected end of expression"), pos)),
+        }
+    }
+}
+
+fn apply(op: &str, l: Value, r: Value, pos: usize) -> Result<Value, EvalError> {
+    // Shifts take the type of their left operand, everything else is done in
+    // the common type of both operands.
+    if op == "<<" || op == ">>" {
+        let limit = if l.t.unsigned() { l.t.bits() } else { 64 };
+        if r.n < 0 || r.n >= limit as i128 {
+            return Err(EvalError::new(format!("shift amount {} is out of range", r.n), pos));
+        }
+        let n = if op == "<<" {
+            try!(checked(l.n.checked_mul(1 << r.n), pos))
+        } else {
+            l.n >> r.n
+        };
+        return Ok(Value::new(n, l.t).to(l.t));
+    }
+    let t = Type::common(l.t, r.t);
+    let (l, r) = (l.to(t).n, r.to(t).n);
+    let n = match op {
+        "*" => try!(checked(l.checked_mul(r), pos)),
+        "/" | "%" => {
+            if r == 0 {
+                return Err(EvalError::new(String::from("division by zero"), pos));
+            }
+            if op == "/" { l / r } else { l % r }
+        }
+        "+" => try!(checked(l.checked_add(r), pos)),
+        "-" => try!(checked(l.checked_sub(r), pos)),
+        "<" => return Ok(Value::int(l < r)),
+        "<=" => return Ok(Value::int(l <= r)),
+        ">" => return Ok(Value::int(l > r)),
+        ">=" => return Ok(Value::int(l >= r)),
+        "==" => return Ok(Value::int(l == r)),
+        "!=" => return Ok(Value::int(l != r)),
+        "&" => l & r,
+        "^" => l ^ r,
+        "|" => l | r,
+        "&&" => return Ok(Value::int(l != 0 && r != 0)),
+        "||" => return Ok(Value::int(l != 0 || r != 0)),
+        _ => unreachable!(),
+    };
+    Ok(Value::new(n, t).to(t))
+}
+
+fn checked(n: Option<i128>, pos: usize) -> Result<i128, EvalError> {
+    match n {
+        Some(n) => Ok(n),
+        None => Err(EvalError::new(String::from("arithmetic overflow"), pos)),
+    }
+}
+
+// The length of the token starting at pos, or 1 if there isn't one so that
+// there is always something to point at.
+fn token_len(s: &str, pos: usize) -> usize {
+    let rest = &s[pos..];
+    let word: usize = rest.chars()
+                          .take_while(|c| c.is_alphanumeric() || *c == '_')
+                          .map(|c| c.len_utf8())
+                          .sum();
+    if word > 0 {
+        return word;
+    }
+    match OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+        Some(op) => op.len(),
+        None => rest.chars().next().map_or(1, |c| c.len_utf8()),
+    }
+}
+
+/// Evaluate a C integer constant expression. Identifiers are resolved using
+/// symbols. The result is always in the range i64::MIN to u64::MAX.
+pub fn eval(s: &str, symbols: &SymbolTable) -> Result<i128, EvalError> {
+    eval_impl(s, symbols).map_err(|mut e| {
+        if e.len == 0 {
+            e.len = token_len(s, e.pos);
+        }
+        e
+    })
+}
+
+fn eval_impl(s: &str, symbols: &SymbolTable) -> Result<i128, EvalError> {
+    let tokens = try!(tokenize(s));
+    let mut p = Parser { tokens: &tokens, symbols: symbols, i: 0, end: s.len() };
+    let n = try!(p.expr(0)).n;
+    if let Some(&(_, pos)) = p.peek() {
+        return Err(EvalError::new(String::from("unexpected trailing input"), pos));
+    }
+    if n < i64::min_value() as i128 || n > u64::max_value() as i128 {
+        let mut e = EvalError::new(format!("{} doesn't fit in 64 bits", n), 0);
+        e.len = s.len();
+        return Err(e);
+    }
+    Ok(n)
+}
+
+#[test]
+fn test_eval_literals() {
+    let st = SymbolTable::new();
+    assert_eq!(eval("0", &st), Ok(0));
+    assert_eq!(eval("42", &st), Ok(42));
+    assert_eq!(eval("0x1F", &st), Ok(31));
+    assert_eq!(eval("1 << 3", &st), Ok(8));
+    assert_eq!(eval("1 << 31", &st), Ok(0x80000000));
+    assert_eq!(eval("0x80000000", &st), Ok(2147483648));
+    assert_eq!(eval("0xFFFFFFFF", &st), Ok(4294967295));
+    assert_eq!(eval("1 << 63", &st), Ok(9223372036854775808));
+    assert_eq!(eval("0xFFFFFFFFFFFFFFFF", &st), Ok(18446744073709551615));
+    assert_eq!(eval("-9223372036854775807 - 1", &st), Ok(-9223372036854775808));
+    assert_eq!(eval("0755", &st), Ok(493));
+    assert_eq!(eval("0b1010", &st), Ok(10));
+    assert_eq!(eval("0x10U", &st), Ok(16));
+    assert_eq!(eval("100UL", &st), Ok(100));
+    assert_eq!(eval("100lu", &st), Ok(100));
+    assert_eq!(eval("1ULL << 40", &st), Ok(1 << 40));
+    assert_eq!(eval("(-110)", &st), Ok(-110));
+    assert!(eval("08", &st).is_err());
+    assert!(eval("0b2", &st).is_err());
+    assert!(eval("1lL", &st).is_err());
+    assert!(eval("1uu", &st).is_err());
+    assert!(eval("1ulu", &st).is_err());
+}
+
+#[test]
+fn test_eval_unsigned() {
+    let st = SymbolTable::new();
+    assert_eq!(eval("~0", &st), Ok(-1));
+    assert_eq!(eval("~0U", &st), Ok(0xFFFFFFFF));
+    assert_eq!(eval("~0UL", &st), Ok(0xFFFFFFFFFFFFFFFF));
+    assert_eq!(eval("-1U", &st), Ok(0xFFFFFFFF));
+    assert_eq!(eval("-1 + 0U", &st), Ok(0xFFFFFFFF));
+    assert_eq!(eval("-1 + 0UL", &st), Ok(0xFFFFFFFFFFFFFFFF));
+    assert_eq!(eval("-1L + 0U", &st), Ok(-1));
+    assert_eq!(eval("0U - 1 < 0", &st), Ok(0));
+    assert_eq!(eval("0xFFFFFFFF + 1", &st), Ok(0));
+    assert_eq!(eval("0xFFFFFFFF + 1L", &st), Ok(0x100000000));
+    assert_eq!(eval("1U << 31", &st), Ok(0x80000000));
+    assert_eq!(eval("~(1 << 31)", &st), Ok(0x7FFFFFFF));
+    assert_eq!(eval("-(1 << 31)", &st), Ok(-0x80000000));
+    assert_eq!(eval("~(1L << 31)", &st), Ok(-0x80000001));
+    assert_eq!(eval("~0x80000000", &st), Ok(0x7FFFFFFF));
+    assert_eq!(eval("-(1 << 40)", &st), Ok(-0x10000000000));
+    assert_eq!(eval("-(1 << 40)", &st), eval("0 - (1 << 40)", &st));
+    assert_eq!(eval("~(1 << 40)", &st), Ok(-0x10000000001));
+    assert_eq!(eval("-0x8000000000000000", &st), Ok(0x8000000000000000));
+    assert_eq!(eval("0xFFFFFFFFFFFFFFFF + 1", &st), Ok(0));
+    assert!(eval("1U << 32", &st).is_err());
+}
+
+#[test]
+fn test_eval_precedence() {
+    let st = SymbolTable::new();
+    assert_eq!(eval("(1 << 3) | (1 << 4)", &st), Ok(24));
+    assert_eq!(eval("1 << 3 | 1 << 4", &st), Ok(24));
+    assert_eq!(eval("1 + 2 * 3", &st), Ok(7));
+    assert_eq!(eval("(1 + 2) * 3", &st), Ok(9));
+    assert_eq!(eval("10 - 4 - 3", &st), Ok(3));
+    assert_eq!(eval("17 % 5 / 2", &st), Ok(1));
+    assert_eq!(eval("0xF0 & 0x3C ^ 0x01", &st), Ok(0x31));
+    assert_eq!(eval("~0", &st), Ok(-1));
+    assert_eq!(eval("-(2 + 3)", &st), Ok(-5));
+    assert_eq!(eval("!0 + !5", &st), Ok(1));
+    assert_eq!(eval("1 < 2 == 1", &st), Ok(1));
+    assert_eq!(eval("1 && 0 || 1", &st), Ok(1));
+}
+
+#[test]
+fn test_eval_errors() {
+    let st = SymbolTable::new();
+    assert_eq!(eval("1 / 0", &st).unwrap_err().pos, 2);
+    assert_eq!(eval("1 << 2 <<", &st).unwrap_err().len, 1);
+    assert_eq!(eval("1 >> 2 2", &st).unwrap_err().len, 1);
+    assert_eq!(eval("1 >> >> 2", &st).unwrap_err().len, 2);
+    assert_eq!(eval("(1 << 2", &st).unwrap_err().pos, 7);
+    assert_eq!(eval("FOO + 1", &st).unwrap_err().pos, 0);
+    assert_eq!(eval("1 2", &st).unwrap_err().pos, 2);
+    assert!(eval("", &st).is_err());
+    assert!(eval("0xZZ", &st).is_err());
+    assert!(eval("1 << 64", &st).is_err());
+    assert!(eval("0x10000000000000000", &st).is_err());
+    assert!(eval("0x7FFFFFFFFFFFFFFF * 4", &st).is_err());
+    assert!(eval("-9223372036854775807 - 2", &st).is_err());
+    assert_eq!(eval("1é", &st).unwrap_err().pos, 1);
+    assert_eq!(eval("é", &st).unwrap_err().pos, 0);
+    assert_eq!(eval("2 × 3", &st).unwrap_err().len, 2);
+}
+
+#[test]
+fn test_eval_symbols() {
+    let mut symbols = SymbolTable::new();
+    symbols.insert(String::from("RTM_BASE"), 16);
+    symbols.insert(String::from("IFF_LOOPBACK"), 1 << 3);
+    symbols.insert(String::from("IFF_POINTOPOINT"), 1 << 4);
+    assert_eq!(eval("RTM_BASE + 4", &symbols), Ok(20));
+    assert_eq!(eval("IFF_LOOPBACK | IFF_POINTOPOINT", &symbols), Ok(24));
+    assert_eq!(eval("RTM_BASE", &symbols), Ok(16));
+    let e = eval("RTM_BASE + RTM_MAX", &symbols).unwrap_err();
+    assert_eq!(e.pos, 11);
+    assert_eq!(e.len, 7);
+    assert_eq!(e.msg, "unknown identifier 'RTM_MAX'");
+}
@@ -4,101 +4,361 @@ extern crate log;
 extern crate env_logger;
 extern crate regex;
 
-use std::cmp::Ordering;
+pub mod build;
+mod error;
+mod expr;
+
+pub use error::{Error, ParseError, Result};
+use expr::SymbolTable;
+use std::collections::HashMap;
 use std::fs::{self, File, OpenOptions};
 use std::io::prelude::*;
-use std::io::{BufReader, BufWriter, Error, ErrorKind, Result};
+use std::io::{BufReader, BufWriter};
 use std::path::PathBuf;
 
 /// Arguments for how to process() an input file.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FileArgs {
-    /// the enum name (Name if not specified)
+    /// the enum name (the C enum name in CamelCase, or Name if not
+    /// specified)
     pub name: Option<String>,
     /// Which traits to derive. Ex: "Debug, PartialEq"
     pub derive: Option<String>,
     /// parse C #define input instead of enum
     pub define: bool,
-    /// implement the Default trait with the first value
+    /// implement the Default trait with the first value, or default_value
     pub default: bool,
+    /// the name or value of the variant that Default returns, as a C
+    /// constant expression (the first value if not specified)
+    pub default_value: Option<String>,
+    /// derive Default and mark the variant with #[default] instead of
+    /// implementing it (needs Rust 1.62)
+    pub default_attr: bool,
     /// implement the std::fmt::Display trait
     pub display: bool,
     /// implement the num::traits::FromPrimitive trait
     pub fromprimative: bool,
     /// implement the std::str::FromStr trait
     pub fromstr: bool,
-    /// hexadecimal output
-    pub hex: bool,
-    /// implement pretty_fmt()
-    pub pretty_fmt: bool,
+    /// implement std::convert::TryFrom for every integer type that can hold
+    /// all of the values
+    pub tryfrom: bool,
+    /// implement From<Name> for the repr type, and as_raw()
+    pub raw: bool,
+    /// hexadecimal output (off if not specified, unless auto turns it on)
+    pub hex: Option<bool>,
+    /// the #[repr] of the enum: u8, u16, u32, u64, i8, i16, i32, i64, C or
+    /// auto (the smallest type that holds every value). If not specified it
+    /// is picked from the values like a C compiler would.
+    pub repr: Option<String>,
+    /// implement pretty_fmt() (off if not specified, unless auto turns it on)
+    pub pretty_fmt: Option<bool>,
+    /// generate a bitflags style struct instead of an enum (the other
+    /// traits aren't implemented for it)
+    pub flags: bool,
+    /// implement serde::Serialize
+    pub serialize: bool,
+    /// implement serde::Deserialize
+    pub deserialize: bool,
+    /// how serde represents a value: "name" for a string, or "int" for the
+    /// integer value ("name" if not specified)
+    pub serde_repr: Option<String>,
+    /// strings for serde to use instead of the C name, by C name
+    pub serde_rename: HashMap<String, String>,
+    /// what to do with a value that has more than one name: "error", "alias"
+    /// to make the later names associated consts, or "drop" to leave them out
+    /// ("alias" if not specified)
+    pub duplicates: Option<String>,
+    /// the prefix to strip from the C names to make the rust names, or "auto"
+    /// for the longest prefix that they all share, see EnumDef::common_prefix()
+    /// (nothing is stripped if not specified)
+    pub strip_prefix: Option<String>,
+    /// the case of the rust names: UpperCamelCase, lowerCamelCase, snake_case
+    /// or SCREAMING_SNAKE_CASE (the case of the C names if not specified)
+    pub case: Option<String>,
+    /// the names that Display, FromStr, pretty_fmt() and serde use: "c" for
+    /// the C names or "rust" for the rust names ("c" if not specified)
+    pub display_names: Option<String>,
+    /// the order of the values in the generated code: "source" for the order
+    /// of the input, "value" or "name" ("value" if not specified)
+    pub order: Option<String>,
+    /// turn on hex output and pretty_fmt() for enums that look like a set of
+    /// flags (see EnumDef::is_flags()). hex or pretty_fmt set either way wins.
+    pub auto: bool,
+    /// which enums to generate, by C name (all of them if not specified)
+    pub enums: Option<Vec<String>>,
+    /// rust enum names to use instead of the default, by C name
+    pub rename: HashMap<String, String>,
 }
 impl Default for FileArgs {
     fn default() -> FileArgs
     {
-        FileArgs{ name: None, derive: None, define: false, default: false, display: false,
-                 fromstr: false, fromprimative: false, hex: false, pretty_fmt: false }
+        FileArgs{ name: None, derive: None, define: false, default: false, default_value: None,
+                 default_attr: false, display: false,
+                 fromstr: false, fromprimative: false, tryfrom: false, raw: false, hex: None,
+                 repr: None, pretty_fmt: None, flags: false, serialize: false, deserialize: false,
+                 serde_repr: None, serde_rename: HashMap::new(), duplicates: None, strip_prefix: None, case: None,
+                 display_names: None, order: None, auto: false,
+                 enums: None,
+                 rename: HashMap::new() }
     }
 }
 
-#[derive(Debug)]
-struct CEnum {
-    i: i32,
-    s: String,
+/// Where something came from in the input.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Span {
+    /// line number, starting at 1
+    pub line: usize,
+    /// column (in bytes), starting at 1
+    pub column: usize,
+    /// length (in bytes)
+    pub len: usize,
 }
-impl CEnum {
-    fn new(i: i32, s: &str) -> CEnum {
-        CEnum { i:i, s: String::from(s) }
-    }
+
+/// One value of an enum.
+///
+/// Variants compare field by field, starting with the name, so two names for
+/// the same value aren't equal.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Variant {
+    /// the C name, for example IFF_UP
+    pub name: String,
+    /// the value
+    pub value: i128,
+    /// where the name is in the input
+    pub span: Span,
+    /// the comment on the same line, or the comment lines just before it
+    pub comment: Option<String>,
+    /// the value as it was written in the input, None if it was left out
+    pub expr: Option<String>,
+    /// the name of the rust variant. If None it is worked out from the C
+    /// name using the naming options when the code is generated.
+    pub ident: Option<String>,
 }
-impl ::std::cmp::Eq for CEnum {}
-impl ::std::cmp::PartialEq for CEnum {
-    fn eq(&self, other: &Self) -> bool {
-        if self.i == other.i {
-            return true;
+impl Variant {
+    /// A variant that didn't come from any input.
+    pub fn new(name: &str, value: i128) -> Variant {
+        Variant { name: String::from(name), value: value, span: Span::default(),
+                  comment: None, expr: None, ident: None }
+    }
+
+    /// The name of the rust variant, the C name if it hasn't been worked out.
+    pub fn ident(&self) -> &str {
+        match self.ident {
+            Some(ref i) => i,
+            None => &self.name,
         }
-        false
     }
 }
-impl ::std::cmp::PartialOrd for CEnum {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        if self.i < other.i {
-            return Some(Ordering::Less);
-        }
-        else if self.i > other.i {
-            return Some(Ordering::Greater);
+/// One enum worth of input: a C enum block, or a group of #defines.
+#[derive(Debug, Clone)]
+pub struct EnumDef {
+    /// the C enum tag or typedef name, or the prefix shared by a group of
+    /// #defines
+    pub name: Option<String>,
+    /// the values, in the order that they were found
+    pub variants: Vec<Variant>,
+    /// where the enum starts in the input
+    pub span: Span,
+    /// the comment lines just before an enum block
+    pub comment: Option<String>,
+}
+impl EnumDef {
+    /// An empty enum that didn't come from any input.
+    pub fn new(name: Option<String>) -> EnumDef {
+        EnumDef { name: name, variants: Vec::new(), span: Span::default(), comment: None }
+    }
+
+    /// The integer type the generated enum is represented as, picked from
+    /// the range of the values.
+    pub fn repr(&self) -> Result<&'static str> {
+        repr_type(&self.variants)
+    }
+
+    /// The longest prefix of whole `_` separated words, like `NETLINK_` or
+    /// `RTM_NEW_`, that the C name of every value starts with and that leaves
+    /// something of each name. This is what `strip_prefix = "auto"` strips.
+    pub fn common_prefix(&self) -> Option<String> {
+        common_prefix(&self.variants)
+    }
+
+    /// Whether the values look like a set of flags rather than a sequence:
+    /// every non-zero value is written out, and is either a power of two
+    /// that no other value has, made of `1<<n` expressions (and no other
+    /// shifts, so `3<<4` doesn't count), or made of the flags before it by
+    /// name (like `BITS_AB = BITS_A | BITS_B`).
+    pub fn is_flags(&self) -> bool {
+        use regex::Regex;
+        let re_shift = Regex::new(r"([[:alnum:]_]+)[:space:]*<<[:space:]*([[:alnum:]_]+)").unwrap();
+        let re_one = Regex::new(r"^1[uUlL]*$").unwrap();
+        let mut seen: Vec<i128> = Vec::new();
+        // every flag seen so far, or'd together
+        let mut all: i128 = 0;
+        for v in self.variants.iter().filter(|v| v.value != 0) {
+            let e = match v.expr {
+                Some(ref e) => e.trim_left_matches('('),
+                None => return false,
+            };
+            let shifts: Vec<_> = re_shift.captures_iter(e).collect();
+            let shifted = !shifts.is_empty() && shifts.len() == e.matches("<<").count() &&
+                          shifts.iter().all(|cap| re_one.is_match(cap.at(1).unwrap()) &&
+                                                  cap.at(2).unwrap().chars().all(|c| c.is_ascii_digit()));
+            let named = !e.starts_with(|c: char| c.is_ascii_digit());
+            let power_of_two = v.value > 0 && v.value.count_ones() == 1;
+            let combined = named && v.value > 0 && v.value & !all == 0;
+            if power_of_two && !seen.contains(&v.value) {
+                seen.push(v.value);
+                all |= v.value;
+            }
+            else if !shifted && !combined {
+                return false
+            }
         }
-        Some(Ordering::Equal)
+        !seen.is_empty()
     }
 }
-impl ::std::cmp::Ord for CEnum {
-    fn cmp(&self, other: &Self) -> Ordering {
-        if self.i < other.i {
-            return Ordering::Less;
+
+/// Options for parse() and parse_all().
+#[derive(Debug, Default)]
+pub struct ParseOptions {
+    /// parse C #define input instead of enum
+    pub define: bool,
+    /// the C name of the enum that parse() returns (the first one if not
+    /// specified)
+    pub enum_name: Option<String>,
+    /// the input file, only used for error messages
+    pub path: Option<PathBuf>,
+}
+
+// Every integer type that an enum can be represented as, with the range of
+// values that it holds, smallest first.
+const INT_TYPES: [(&'static str, i128, i128); 8] = [
+    ("u8", 0, 0xFF),
+    ("i8", -0x80, 0x7F),
+    ("u16", 0, 0xFFFF),
+    ("i16", -0x8000, 0x7FFF),
+    ("u32", 0, 0xFFFFFFFF),
+    ("i32", -0x80000000, 0x7FFFFFFF),
+    ("u64", 0, 0xFFFFFFFFFFFFFFFF),
+    ("i64", -0x8000000000000000, 0x7FFFFFFFFFFFFFFF),
+];
+
+// The smallest and the largest value, 0 for both if there aren't any.
+fn value_range(vec: &Vec<Variant>) -> (i128, i128) {
+    let min = vec.iter().map(|v| v.value).min().unwrap_or(0);
+    let max = vec.iter().map(|v| v.value).max().unwrap_or(0);
+    (min, max)
+}
+
+// The types from INT_TYPES that hold every value, smallest first.
+fn holding_types(vec: &Vec<Variant>) -> Vec<&'static str> {
+    let (min, max) = value_range(vec);
+    INT_TYPES.iter().filter(|t| min >= t.1 && max <= t.2).map(|t| t.0).collect()
+}
+
+// The width of one of the INT_TYPES in bits.
+fn int_bits(t: &str) -> u32 {
+    t[1..].parse().unwrap()
+}
+
+/// Pick the integer type used to represent an enum from the range of its
+/// values. Like a C compiler this prefers i32 and only goes wider when it has
+/// to.
+fn repr_type(vec: &Vec<Variant>) -> Result<&'static str> {
+    let holding = holding_types(vec);
+    match ["i32", "u32", "i64", "u64"].iter().find(|t| holding.contains(t)) {
+        Some(t) => Ok(t),
+        None => {
+            let (min, max) = value_range(vec);
+            Err(Error::Generate(format!("values from {} to {} don't fit in any integer type",
+                                        min, max)))
         }
-        else if self.i > other.i {
-            return Ordering::Greater;
+    }
+}
+
+/// Pick the integer type used to represent an enum, following the repr
+/// option. Without it (or with C) this is repr_type(), auto picks the smallest
+/// type that holds every value, and any other type is used as is as long as
+/// every value fits in it.
+fn choose_repr(vec: &Vec<Variant>, repr: Option<&String>) -> Result<&'static str> {
+    let (min, max) = value_range(vec);
+    let repr = match repr {
+        Some(r) => &r[..],
+        None => return repr_type(vec),
+    };
+    match repr {
+        "C" if max > i64::max_value() as i128 => {
+            Err(Error::Generate(format!("values from {} to {} don't fit in a #[repr(C)] enum",
+                                        min, max)))
         }
-        Ordering::Equal
+        "C" => repr_type(vec),
+        "auto" => match holding_types(vec).first() {
+            Some(t) => Ok(t),
+            None => Err(Error::Generate(format!("values from {} to {} don't fit in any \
+                                                integer type", min, max))),
+        },
+        _ => match INT_TYPES.iter().find(|t| t.0 == repr) {
+            Some(t) if holding_types(vec).contains(&t.0) => Ok(t.0),
+            Some(t) => Err(Error::Generate(format!("values from {} to {} don't fit in {}",
+                                                   min, max, t.0))),
+            None => Err(Error::config(format!("repr should be u8, u16, u32, u64, i8, i16, \
+                                              i32, i64, C or auto, not {}", repr))),
+        },
+    }
+}
+
+/// The unsigned type that is the same width as repr.
+fn unsigned_type(repr: &str) -> &'static str {
+    match repr {
+        "i8" | "u8" => "u8",
+        "i16" | "u16" => "u16",
+        "i64" | "u64" => "u64",
+        _ => "u32",
+    }
+}
+
+/// Format a value in hex for a match pattern. Casts aren't allowed in
+/// patterns so negative values are written as a negated literal, -0x1.
+fn hex_pattern(i: i128) -> String {
+    if i < 0 {
+        format!("-0x{:X}", -i)
+    }
+    else {
+        format!("0x{:X}", i)
+    }
+}
+
+/// Format a value in hex for an enum discriminant. Negative values are
+/// written as their two's complement at the width of repr, cast back to repr,
+/// so -1 in an i32 enum is 0xFFFFFFFFu32 as i32.
+fn hex_discriminant(i: i128, repr: &str) -> String {
+    if i < 0 {
+        let unsigned = unsigned_type(repr);
+        let bits = int_bits(unsigned);
+        format!("0x{:X}{} as {}", i.rem_euclid(1 << bits), unsigned, repr)
+    }
+    else {
+        format!("0x{:X}", i)
     }
 }
 
 trait FormatOutput {
-    fn write(&self, w: &mut Write, name: &String, hex: bool, vec: &Vec<CEnum>) -> Result<()>;
+    fn write(&self, w: &mut Write, name: &String, file_args: &FileArgs, vec: &Vec<Variant>) -> Result<()>;
 }
 
 struct FormatOutputFromPrimative;
 impl FormatOutput for FormatOutputFromPrimative {
-    fn write(&self, w: &mut Write, name: &String, hex: bool, vec: &Vec<CEnum>) -> Result<()> {
+    fn write(&self, w: &mut Write, name: &String, file_args: &FileArgs, vec: &Vec<Variant>) -> Result<()> {
         try!(write!(w, "impl ::num::traits::FromPrimitive for {} {{\n", name));
         try!(write!(w, "    #[allow(dead_code)]\n"));
         try!(write!(w, "    fn from_i64(n: i64) -> Option<Self> {{\n"));
         try!(write!(w, "        match n {{\n"));
-        for v in vec {
-            if hex {
-                try!(write!(w, "            0x{:X} => Some({}::{}),\n", v.i, name, v.s));
+        for v in vec.iter().filter(|v| v.value <= i64::max_value() as i128) {
+            if file_args.hex == Some(true) {
+                try!(write!(w, "            {} => Some({}::{}),\n", hex_pattern(v.value), name, v.ident()));
             }
             else {
-                try!(write!(w, "            {} => Some({}::{}),\n", v.i, name, v.s));
+                try!(write!(w, "            {} => Some({}::{}),\n", v.value, name, v.ident()));
             }
         }
         try!(write!(w, "            _ => None\n"));
@@ -107,12 +367,12 @@ impl FormatOutput for FormatOutputFromPrimative {
         try!(write!(w, "    #[allow(dead_code)]\n"));
         try!(write!(w, "    fn from_u64(n: u64) -> Option<Self> {{\n"));
         try!(write!(w, "        match n {{\n"));
-        for v in vec {
-            if hex {
-                try!(write!(w, "            0x{:X} => Some({}::{}),\n", v.i, name, v.s));
+        for v in vec.iter().filter(|v| v.value >= 0) {
+            if file_args.hex == Some(true) {
+                try!(write!(w, "            {} => Some({}::{}),\n", hex_pattern(v.value), name, v.ident()));
             }
             else {
-                try!(write!(w, "            {} => Some({}::{}),\n", v.i, name, v.s));
+                try!(write!(w, "            {} => Some({}::{}),\n", v.value, name, v.ident()));
             }
         }
         try!(write!(w, "            _ => None\n"));
@@ -123,44 +383,447 @@ impl FormatOutput for FormatOutputFromPrimative {
     }
 }
 
+struct FormatOutputTryFrom;
+impl FormatOutput for FormatOutputTryFrom {
+    fn write(&self, w: &mut Write, name: &String, file_args: &FileArgs, vec: &Vec<Variant>) -> Result<()> {
+        let error = format!("TryFrom{}Error", name);
+        try!(write!(w, "/// The error returned when converting an integer that isn't a {}.\n", name));
+        try!(write!(w, "#[derive(Debug, Clone, Copy, PartialEq, Eq)]\n"));
+        try!(write!(w, "pub struct {}<T>(pub T);\n", error));
+        try!(write!(w, "impl<T: ::std::fmt::Display> ::std::fmt::Display for {}<T> {{\n", error));
+        try!(write!(w, "    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {{\n"));
+        try!(write!(w, "        write!(f, \"{{}} isn't a valid {}\", self.0)\n", name));
+        try!(write!(w, "    }}\n"));
+        try!(write!(w, "}}\n"));
+        try!(write!(w, "impl<T: ::std::fmt::Debug + ::std::fmt::Display> ::std::error::Error for {}<T> {{}}\n", error));
+        for t in &holding_types(vec) {
+            try!(write!(w, "impl ::std::convert::TryFrom<{}> for {} {{\n", t, name));
+            try!(write!(w, "    type Error = {}<{}>;\n", error, t));
+            try!(write!(w, "    #[allow(dead_code)]\n"));
+            try!(write!(w, "    fn try_from(n: {}) -> Result<Self, Self::Error> {{\n", t));
+            try!(write!(w, "        match n {{\n"));
+            for v in vec {
+                if file_args.hex == Some(true) {
+                    try!(write!(w, "            {} => Ok({}::{}),\n", hex_pattern(v.value), name, v.ident()));
+                }
+                else {
+                    try!(write!(w, "            {} => Ok({}::{}),\n", v.value, name, v.ident()));
+                }
+            }
+            try!(write!(w, "            _ => Err({}(n))\n", error));
+            try!(write!(w, "        }}\n"));
+            try!(write!(w, "    }}\n"));
+            try!(write!(w, "}}\n"));
+        }
+        Ok(())
+    }
+}
+
+// A bitflags style struct instead of an enum, for values that are ORed
+// together.
+struct FormatOutputFlags;
+impl FormatOutput for FormatOutputFlags {
+    fn write(&self, w: &mut Write, name: &String, file_args: &FileArgs, vec: &Vec<Variant>) -> Result<()> {
+        let bits_type = unsigned_type(try!(choose_repr(vec, file_args.repr.as_ref())));
+        let bits = int_bits(bits_type);
+        // negative values, like ~0, are their two's complement
+        let values: Vec<i128> = vec.iter().map(|v| v.value.rem_euclid(1 << bits)).collect();
+        let all = values.iter().fold(0, |a, v| a | v);
+        let fmt = |i: i128| if file_args.hex == Some(true) { format!("0x{:X}", i) } else { format!("{}", i) };
+
+        try!(write!(w, "#[allow(dead_code, non_camel_case_types)]\n"));
+        if let Some(ref s) = file_args.derive {
+            try!(write!(w, "#[derive({})]\n", s));
+        }
+        try!(write!(w, "pub struct {}({});\n", name, bits_type));
+        try!(write!(w, "#[allow(dead_code, non_upper_case_globals)]\n"));
+        try!(write!(w, "impl {} {{\n", name));
+        for (v, i) in vec.iter().zip(values.iter()) {
+            try!(write!(w, "    pub const {}: {} = {}({});\n", v.ident(), name, name, fmt(*i)));
+        }
+        try!(write!(w, "\n"));
+        // the values with the most bits set come first, so that named() picks
+        // a mask before the flags it's made of
+        let mut widest: Vec<(&Variant, i128)> = vec.iter().zip(values.iter().cloned()).collect();
+        widest.sort_by(|a, b| b.1.count_ones().cmp(&a.1.count_ones()));
+        try!(write!(w, "    fn names() -> &'static [(&'static str, {})] {{\n", bits_type));
+        try!(write!(w, "        &[\n"));
+        for &(v, i) in &widest {
+            try!(write!(w, "            (\"{}\", {}),\n", string_name(v, file_args), fmt(i)));
+        }
+        try!(write!(w, "        ]\n"));
+        try!(write!(w, "    }}\n"));
+        try!(write!(w, "    /// No flags set.\n"));
+        try!(write!(w, "    pub const fn empty() -> {} {{\n", name));
+        try!(write!(w, "        {}(0)\n", name));
+        try!(write!(w, "    }}\n"));
+        try!(write!(w, "    /// Every flag set.\n"));
+        try!(write!(w, "    pub const fn all() -> {} {{\n", name));
+        try!(write!(w, "        {}({})\n", name, fmt(all)));
+        try!(write!(w, "    }}\n"));
+        try!(write!(w, "    /// The raw value.\n"));
+        try!(write!(w, "    pub const fn bits(&self) -> {} {{\n", bits_type));
+        try!(write!(w, "        self.0\n"));
+        try!(write!(w, "    }}\n"));
+        try!(write!(w, "    /// The flags in bits, or None if any bit isn't a flag.\n"));
+        try!(write!(w, "    pub const fn from_bits(bits: {}) -> Option<{}> {{\n", bits_type, name));
+        try!(write!(w, "        if bits & !{}::all().0 == 0 {{\n", name));
+        try!(write!(w, "            Some({}(bits))\n", name));
+        try!(write!(w, "        }}\n"));
+        try!(write!(w, "        else {{\n"));
+        try!(write!(w, "            None\n"));
+        try!(write!(w, "        }}\n"));
+        try!(write!(w, "    }}\n"));
+        try!(write!(w, "    /// The flags in bits, dropping any bit that isn't a flag.\n"));
+        try!(write!(w, "    pub const fn from_bits_truncate(bits: {}) -> {} {{\n", bits_type, name));
+        try!(write!(w, "        {}(bits & {}::all().0)\n", name, name));
+        try!(write!(w, "    }}\n"));
+        try!(write!(w, "    /// Whether no flags are set.\n"));
+        try!(write!(w, "    pub const fn is_empty(&self) -> bool {{\n"));
+        try!(write!(w, "        self.0 == 0\n"));
+        try!(write!(w, "    }}\n"));
+        try!(write!(w, "    /// Whether every flag in other is set.\n"));
+        try!(write!(w, "    pub const fn contains(&self, other: {}) -> bool {{\n", name));
+        try!(write!(w, "        self.0 & other.0 == other.0\n"));
+        try!(write!(w, "    }}\n"));
+        try!(write!(w, "    /// Set the flags in other.\n"));
+        try!(write!(w, "    pub fn insert(&mut self, other: {}) {{\n", name));
+        try!(write!(w, "        self.0 |= other.0;\n"));
+        try!(write!(w, "    }}\n"));
+        try!(write!(w, "    /// Clear the flags in other.\n"));
+        try!(write!(w, "    pub fn remove(&mut self, other: {}) {{\n", name));
+        try!(write!(w, "        self.0 &= !other.0;\n"));
+        try!(write!(w, "    }}\n"));
+        try!(write!(w, "    // The named flags that are set, along with their names. Masks are\n"));
+        try!(write!(w, "    // matched first, and a flag whose bits have already been named is\n"));
+        try!(write!(w, "    // skipped.\n"));
+        try!(write!(w, "    fn named(&self) -> Vec<(&'static str, {})> {{\n", name));
+        try!(write!(w, "        let mut remaining = self.0;\n"));
+        try!(write!(w, "        let mut v = Vec::new();\n"));
+        try!(write!(w, "        for &(n, bits) in {}::names() {{\n", name));
+        try!(write!(w, "            if bits != 0 && remaining & bits == bits {{\n"));
+        try!(write!(w, "                remaining &= !bits;\n"));
+        try!(write!(w, "                v.push((n, {}(bits)));\n", name));
+        try!(write!(w, "            }}\n"));
+        try!(write!(w, "        }}\n"));
+        try!(write!(w, "        v\n"));
+        try!(write!(w, "    }}\n"));
+        try!(write!(w, "    /// Each of the named flags that is set.\n"));
+        try!(write!(w, "    pub fn iter(&self) -> impl Iterator<Item = {}> {{\n", name));
+        try!(write!(w, "        self.named().into_iter().map(|(_, flag)| flag)\n"));
+        try!(write!(w, "    }}\n"));
+        try!(write!(w, "}}\n"));
+
+        let ops = [("BitOr", "bitor", "self.0 | other.0"),
+                   ("BitAnd", "bitand", "self.0 & other.0"),
+                   ("BitXor", "bitxor", "self.0 ^ other.0"),
+                   ("Sub", "sub", "self.0 & !other.0")];
+        for &(t, f, e) in ops.iter() {
+            try!(write!(w, "impl ::std::ops::{} for {} {{\n", t, name));
+            try!(write!(w, "    type Output = {};\n", name));
+            try!(write!(w, "    fn {}(self, other: {}) -> {} {{\n", f, name, name));
+            try!(write!(w, "        {}({})\n", name, e));
+            try!(write!(w, "    }}\n"));
+            try!(write!(w, "}}\n"));
+        }
+        try!(write!(w, "impl ::std::ops::Not for {} {{\n", name));
+        try!(write!(w, "    type Output = {};\n", name));
+        try!(write!(w, "    fn not(self) -> {} {{\n", name));
+        try!(write!(w, "        {}::from_bits_truncate(!self.0)\n", name));
+        try!(write!(w, "    }}\n"));
+        try!(write!(w, "}}\n"));
+
+        try!(write!(w, "impl ::std::fmt::Display for {} {{\n", name));
+        try!(write!(w, "    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {{\n"));
+        try!(write!(w, "        if self.0 == 0 {{\n"));
+        try!(write!(w, "            return match {}::names().iter().find(|&&(_, bits)| bits == 0) {{\n", name));
+        try!(write!(w, "                Some(&(n, _)) => f.write_str(n),\n"));
+        try!(write!(w, "                None => f.write_str(\"(empty)\"),\n"));
+        try!(write!(w, "            }};\n"));
+        try!(write!(w, "        }}\n"));
+        try!(write!(w, "        let named = self.named();\n"));
+        try!(write!(w, "        let mut remaining = self.0;\n"));
+        try!(write!(w, "        for (i, &(n, ref flag)) in named.iter().enumerate() {{\n"));
+        try!(write!(w, "            if i > 0 {{\n"));
+        try!(write!(w, "                f.write_str(\"|\")?;\n"));
+        try!(write!(w, "            }}\n"));
+        try!(write!(w, "            f.write_str(n)?;\n"));
+        try!(write!(w, "            remaining &= !flag.0;\n"));
+        try!(write!(w, "        }}\n"));
+        try!(write!(w, "        if remaining != 0 {{\n"));
+        try!(write!(w, "            if !named.is_empty() {{\n"));
+        try!(write!(w, "                f.write_str(\"|\")?;\n"));
+        try!(write!(w, "            }}\n"));
+        try!(write!(w, "            write!(f, \"{{:#x}}\", remaining)?;\n"));
+        try!(write!(w, "        }}\n"));
+        try!(write!(w, "        Ok(())\n"));
+        try!(write!(w, "    }}\n"));
+        try!(write!(w, "}}\n"));
+        Ok(())
+    }
+}
+
+struct FormatOutputRaw;
+impl FormatOutput for FormatOutputRaw {
+    fn write(&self, w: &mut Write, name: &String, file_args: &FileArgs, vec: &Vec<Variant>) -> Result<()> {
+        let repr = try!(choose_repr(vec, file_args.repr.as_ref()));
+        try!(write!(w, "impl {} {{\n", name));
+        try!(write!(w, "    /// The value of this {} as a {}.\n", name, repr));
+        try!(write!(w, "    #[allow(dead_code)]\n"));
+        try!(write!(w, "    pub const fn as_raw(&self) -> {} {{\n", repr));
+        try!(write!(w, "        match *self {{\n"));
+        for v in vec {
+            if file_args.hex == Some(true) {
+                try!(write!(w, "            {}::{} => {},\n", name, v.ident(), hex_discriminant(v.value, repr)));
+            }
+            else {
+                try!(write!(w, "            {}::{} => {},\n", name, v.ident(), v.value));
+            }
+        }
+        try!(write!(w, "        }}\n"));
+        try!(write!(w, "    }}\n"));
+        try!(write!(w, "}}\n"));
+        try!(write!(w, "impl From<{}> for {} {{\n", name, repr));
+        try!(write!(w, "    fn from(n: {}) -> {} {{\n", name, repr));
+        try!(write!(w, "        n.as_raw()\n"));
+        try!(write!(w, "    }}\n"));
+        try!(write!(w, "}}\n"));
+        Ok(())
+    }
+}
+
+// A Display wrapper for a set of flags made up of the values, and a
+// pretty_fmt() that returns one. Values with the most bits set are matched
+// first, so a mask is named rather than the flags it is made of, and any bits
+// left over are written in hex.
 struct FormatOutputPrettyFmt;
 impl FormatOutput for FormatOutputPrettyFmt {
-    #[allow(unused_variables)]
-    fn write(&self, w: &mut Write, name: &String, hex: bool, vec: &Vec<CEnum>) -> Result<()> {
-        try!(write!(w, "impl {} {{\n", name));
-        try!(write!(w, "    fn pretty_fmt(f: &mut ::std::fmt::Formatter, flags: u32) -> ::std::fmt::Result {{\n"));
-        try!(write!(w, "        let mut shift: u32 = 0;\n"));
-        try!(write!(w, "        let mut result: u32 = 1<<shift;\n"));
-        try!(write!(w, "        let mut found = false;\n"));
-        // This should never fail because we check in main() to make sure that
-        // it isn't empty.
-        try!(write!(w, "        while result <= {}::{} as u32 {{\n", name, vec.last().unwrap().s));
-        try!(write!(w, "            let tmp = result & flags;\n"));
-        try!(write!(w, "            if tmp > 0 {{\n"));
-        try!(write!(w, "                if found {{\n"));
-        try!(write!(w, "                    try!(write!(f, \"|\"));\n"));
+    fn write(&self, w: &mut Write, name: &String, file_args: &FileArgs, vec: &Vec<Variant>) -> Result<()> {
+        let flags_type = unsigned_type(try!(choose_repr(vec, file_args.repr.as_ref())));
+        let bits = int_bits(flags_type);
+        let wrapper = format!("{}PrettyFmt", name);
+        // negative values, like ~0, are their two's complement
+        let mut flags: Vec<(&str, i128)> = vec.iter()
+            .map(|v| (string_name(v, file_args), v.value.rem_euclid(1 << bits)))
+            .filter(|&(_, i)| i != 0)
+            .collect();
+        flags.sort_by(|a, b| b.1.count_ones().cmp(&a.1.count_ones()));
+        let zero = match vec.iter().find(|v| v.value == 0) {
+            Some(v) => string_name(v, file_args),
+            None => "0",
+        };
+
+        try!(write!(w, "/// Formats a set of {} flags, see {}::pretty_fmt().\n", name, name));
+        try!(write!(w, "#[allow(dead_code)]\n"));
+        try!(write!(w, "pub struct {}(pub {});\n", wrapper, flags_type));
+        try!(write!(w, "impl ::std::fmt::Display for {} {{\n", wrapper));
+        try!(write!(w, "    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {{\n"));
+        try!(write!(w, "        const FLAGS: [(&'static str, {}); {}] = [\n", flags_type, flags.len()));
+        for &(n, i) in &flags {
+            if file_args.hex == Some(true) {
+                try!(write!(w, "            (\"{}\", 0x{:X}),\n", n, i));
+            }
+            else {
+                try!(write!(w, "            (\"{}\", {}),\n", n, i));
+            }
+        }
+        try!(write!(w, "        ];\n"));
+        try!(write!(w, "        if self.0 == 0 {{\n"));
+        try!(write!(w, "            return f.write_str(\"{}\")\n", zero));
+        try!(write!(w, "        }}\n"));
+        try!(write!(w, "        let mut remaining = self.0;\n"));
+        try!(write!(w, "        let mut first = true;\n"));
+        try!(write!(w, "        for &(name, bits) in FLAGS.iter() {{\n"));
+        try!(write!(w, "            if self.0 & bits == bits && remaining & bits != 0 {{\n"));
+        try!(write!(w, "                if !first {{\n"));
+        try!(write!(w, "                    f.write_str(\"|\")?;\n"));
         try!(write!(w, "                }}\n"));
-        try!(write!(w, "                let flag = {}::from_u32(tmp).unwrap();\n", name));
-        try!(write!(w, "                try!(write!(f, \"{{}}\", flag));\n"));
-        try!(write!(w, "                found = true;\n"));
+        try!(write!(w, "                f.write_str(name)?;\n"));
+        try!(write!(w, "                remaining &= !bits;\n"));
+        try!(write!(w, "                first = false;\n"));
+        try!(write!(w, "            }}\n"));
+        try!(write!(w, "        }}\n"));
+        try!(write!(w, "        if remaining != 0 {{\n"));
+        try!(write!(w, "            if !first {{\n"));
+        try!(write!(w, "                f.write_str(\"|\")?;\n"));
         try!(write!(w, "            }}\n"));
-        try!(write!(w, "            shift += 1;\n"));
-        try!(write!(w, "            result = 1<<shift;\n"));
+        try!(write!(w, "            write!(f, \"{{:#x}}\", remaining)?;\n"));
         try!(write!(w, "        }}\n"));
-        try!(write!(w, "        write!(f, \"\")\n"));
+        try!(write!(w, "        Ok(())\n"));
+        try!(write!(w, "    }}\n"));
+        try!(write!(w, "}}\n"));
+        try!(write!(w, "impl {} {{\n", name));
+        try!(write!(w, "    /// Display flags made up of {} values, with any unknown bits in hex.\n", name));
+        try!(write!(w, "    #[allow(dead_code)]\n"));
+        try!(write!(w, "    pub fn pretty_fmt(flags: {}) -> {} {{\n", flags_type, wrapper));
+        try!(write!(w, "        {}(flags)\n", wrapper));
+        try!(write!(w, "    }}\n"));
+        try!(write!(w, "}}\n"));
+        Ok(())
+    }
+}
+
+// Whether serde should represent values as integers rather than names.
+fn serde_int(file_args: &FileArgs) -> Result<bool> {
+    match file_args.serde_repr.as_ref().map(|s| &s[..]) {
+        None | Some("name") => Ok(false),
+        Some("int") => Ok(true),
+        Some(r) => Err(Error::config(format!("serde_repr should be name or int, not {}", r))),
+    }
+}
+
+// The string serde uses for each value: its serde_rename, or its C name.
+fn serde_names<'a>(vec: &'a Vec<Variant>, file_args: &'a FileArgs) -> Result<Vec<&'a str>> {
+    let mut names: Vec<&str> = Vec::new();
+    for v in vec {
+        let n = match file_args.serde_rename.get(&v.name) {
+            Some(n) => n,
+            None => string_name(v, file_args),
+        };
+        if names.contains(&n) {
+            return Err(Error::Generate(format!("more than one value would be serialized \
+                                               as \"{}\"", n)))
+        }
+        names.push(n);
+    }
+    Ok(names)
+}
+
+struct FormatOutputSerialize;
+impl FormatOutput for FormatOutputSerialize {
+    fn write(&self, w: &mut Write, name: &String, file_args: &FileArgs, vec: &Vec<Variant>) -> Result<()> {
+        try!(write!(w, "impl ::serde::Serialize for {} {{\n", name));
+        try!(write!(w, "    fn serialize<S: ::serde::Serializer>(&self, serializer: S) \
+                        -> ::std::result::Result<S::Ok, S::Error> {{\n"));
+        if try!(serde_int(file_args)) {
+            let repr = try!(choose_repr(vec, file_args.repr.as_ref()));
+            try!(write!(w, "        serializer.serialize_{}(match *self {{\n", repr));
+            for v in vec {
+                if file_args.hex == Some(true) {
+                    try!(write!(w, "            {}::{} => {},\n", name, v.ident(),
+                                hex_discriminant(v.value, repr)));
+                }
+                else {
+                    try!(write!(w, "            {}::{} => {},\n", name, v.ident(), v.value));
+                }
+            }
+        }
+        else {
+            let names = try!(serde_names(vec, file_args));
+            try!(write!(w, "        serializer.serialize_str(match *self {{\n"));
+            for (v, n) in vec.iter().zip(names.iter()) {
+                try!(write!(w, "            {}::{} => {:?},\n", name, v.ident(), n));
+            }
+        }
+        try!(write!(w, "        }})\n"));
+        try!(write!(w, "    }}\n"));
+        try!(write!(w, "}}\n"));
+        Ok(())
+    }
+}
+
+// Deserialize goes through a Visitor, so that a value that isn't allowed is
+// reported along with the values that are.
+struct FormatOutputDeserialize;
+impl FormatOutput for FormatOutputDeserialize {
+    fn write(&self, w: &mut Write, name: &String, file_args: &FileArgs, vec: &Vec<Variant>) -> Result<()> {
+        let int = try!(serde_int(file_args));
+        try!(write!(w, "impl<'de> ::serde::Deserialize<'de> for {} {{\n", name));
+        try!(write!(w, "    fn deserialize<D: ::serde::Deserializer<'de>>(deserializer: D) \
+                        -> ::std::result::Result<Self, D::Error> {{\n"));
+        try!(write!(w, "        struct Visitor;\n"));
+        try!(write!(w, "        impl<'de> ::serde::de::Visitor<'de> for Visitor {{\n"));
+        try!(write!(w, "            type Value = {};\n", name));
+        try!(write!(w, "            fn expecting(&self, f: &mut ::std::fmt::Formatter) \
+                        -> ::std::fmt::Result {{\n"));
+        if int {
+            let repr = try!(choose_repr(vec, file_args.repr.as_ref()));
+            let values: Vec<String> = vec.iter().map(|v| match file_args.hex == Some(true) {
+                true => hex_pattern(v.value),
+                false => v.value.to_string(),
+            }).collect();
+            // the allowed values are listed in decimal, like most formats write them
+            let allowed: Vec<String> = vec.iter().map(|v| v.value.to_string()).collect();
+            try!(write!(w, "                f.write_str(\"one of {}\")\n", allowed.join(", ")));
+            try!(write!(w, "            }}\n"));
+            for &(t, unexpected) in &[("i64", "Signed"), ("u64", "Unsigned")] {
+                try!(write!(w, "            fn visit_{}<E: ::serde::de::Error>(self, n: {}) \
+                                -> ::std::result::Result<{}, E> {{\n", t, t, name));
+                try!(write!(w, "                match n {{\n"));
+                let fits = vec.iter().zip(values.iter()).filter(|&(v, _)| match t {
+                    "i64" => v.value <= i64::max_value() as i128,
+                    _ => v.value >= 0,
+                });
+                for (v, value) in fits {
+                    try!(write!(w, "                    {} => Ok({}::{}),\n", value, name, v.ident()));
+                }
+                try!(write!(w, "                    _ => Err(E::invalid_value(\
+                                ::serde::de::Unexpected::{}(n), &self)),\n", unexpected));
+                try!(write!(w, "                }}\n"));
+                try!(write!(w, "            }}\n"));
+            }
+            try!(write!(w, "        }}\n"));
+            try!(write!(w, "        deserializer.deserialize_{}(Visitor)\n", repr));
+        }
+        else {
+            let names = try!(serde_names(vec, file_args));
+            let quoted: Vec<String> = names.iter().map(|n| format!("`{}`", n)).collect();
+            try!(write!(w, "                f.write_str({:?})\n",
+                        format!("one of {}", quoted.join(", "))));
+            try!(write!(w, "            }}\n"));
+            try!(write!(w, "            fn visit_str<E: ::serde::de::Error>(self, s: &str) \
+                            -> ::std::result::Result<{}, E> {{\n", name));
+            try!(write!(w, "                const VALUES: &'static [&'static str] = &{:?};\n", names));
+            try!(write!(w, "                match s {{\n"));
+            for (v, n) in vec.iter().zip(names.iter()) {
+                try!(write!(w, "                    {:?} => Ok({}::{}),\n", n, name, v.ident()));
+            }
+            try!(write!(w, "                    _ => Err(E::unknown_variant(s, VALUES)),\n"));
+            try!(write!(w, "                }}\n"));
+            try!(write!(w, "            }}\n"));
+            try!(write!(w, "        }}\n"));
+            try!(write!(w, "        deserializer.deserialize_str(Visitor)\n"));
+        }
         try!(write!(w, "    }}\n"));
         try!(write!(w, "}}\n"));
         Ok(())
     }
 }
 
+// The variant that Default returns: the one with the name or value given by
+// file_args.default_value, or the first one. The first variant with the value
+// is used, so if the variants come before their aliases an alias gives the
+// variant that it's an alias of.
+fn default_variant<'a>(vec: &'a Vec<Variant>, name: &str,
+                       file_args: &FileArgs) -> Result<&'a Variant> {
+    let d = match file_args.default_value {
+        Some(ref d) => d,
+        None => return Ok(&vec[0]),
+    };
+    let mut symbols = SymbolTable::new();
+    for v in vec {
+        symbols.insert(v.name.clone(), v.value);
+        symbols.insert(String::from(v.ident()), v.value);
+    }
+    let value = match expr::eval(d, &symbols) {
+        Ok(value) => value,
+        Err(e) => return Err(Error::config(format!("can't use {} as the default for {}: {}",
+                                                   d, name, e.msg))),
+    };
+    match vec.iter().find(|v| v.value == value) {
+        Some(v) => Ok(v),
+        None => Err(Error::config(format!("{} has no value {} to use as the default",
+                                          name, value))),
+    }
+}
+
 struct FormatOutputDefault;
 impl FormatOutput for FormatOutputDefault {
-    #[allow(unused_variables)]
-    fn write(&self, w: &mut Write, name: &String, hex: bool, vec: &Vec<CEnum>) -> Result<()> {
+    fn write(&self, w: &mut Write, name: &String, file_args: &FileArgs, vec: &Vec<Variant>) -> Result<()> {
+        let default = try!(default_variant(vec, name, file_args));
         try!(write!(w, "impl Default for {} {{\n", name));
         try!(write!(w, "    fn default() -> {} {{\n", name));
-        try!(write!(w, "        {}::{}\n", name, vec[0].s));
+        try!(write!(w, "        {}::{}\n", name, default.ident()));
         try!(write!(w, "    }}\n"));
         try!(write!(w, "}}\n"));
         Ok(())
@@ -170,13 +833,14 @@ impl FormatOutput for FormatOutputDefault {
 struct FormatOutputDisplay;
 impl FormatOutput for FormatOutputDisplay {
     #[allow(unused_variables)]
-    fn write(&self, w: &mut Write, name: &String, hex: bool, vec: &Vec<CEnum>) -> Result<()> {
+    fn write(&self, w: &mut Write, name: &String, file_args: &FileArgs, vec: &Vec<Variant>) -> Result<()> {
         try!(write!(w, "impl ::std::fmt::Display for {} {{\n", name));
         try!(write!(w, "    #[allow(dead_code)]\n"));
         try!(write!(w, "    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {{\n"));
         try!(write!(w, "        match *self {{\n"));
         for v in vec {
-            try!(write!(w, "            {}::{} => write!(f, \"{}\"),\n", name, v.s, v.s));
+            try!(write!(w, "            {}::{} => write!(f, \"{}\"),\n", name, v.ident(),
+                        string_name(v, file_args)));
         }
         try!(write!(w, "        }}\n"));
         try!(write!(w, "    }}\n"));
@@ -188,16 +852,32 @@ impl FormatOutput for FormatOutputDisplay {
 struct FormatOutputFromStr;
 impl FormatOutput for FormatOutputFromStr {
     #[allow(unused_variables)]
-    fn write(&self, w: &mut Write, name: &String, hex: bool, vec: &Vec<CEnum>) -> Result<()> {
+    fn write(&self, w: &mut Write, name: &String, file_args: &FileArgs, vec: &Vec<Variant>) -> Result<()> {
+        let error = format!("Parse{}Error", name);
+        let names: Vec<&str> = vec.iter().map(|v| string_name(v, file_args)).collect();
+        try!(write!(w, "/// The error returned when parsing a string that isn't the name of a {}.\n", name));
+        try!(write!(w, "/// Holds the string.\n"));
+        try!(write!(w, "#[derive(Debug, Clone, PartialEq, Eq)]\n"));
+        try!(write!(w, "pub struct {}(pub String);\n", error));
+        try!(write!(w, "impl ::std::fmt::Display for {} {{\n", error));
+        try!(write!(w, "    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {{\n"));
+        try!(write!(w, "        write!(f, \"{{:?}} isn't a valid {}, expected one of {}\", self.0)\n",
+                    name, names.join(", ")));
+        try!(write!(w, "    }}\n"));
+        try!(write!(w, "}}\n"));
+        try!(write!(w, "impl ::std::error::Error for {} {{}}\n", error));
         try!(write!(w, "impl ::std::str::FromStr for {} {{\n", name));
-        try!(write!(w, "    type Err = ();\n"));
+        try!(write!(w, "    type Err = {};\n", error));
         try!(write!(w, "    #[allow(dead_code)]\n"));
         try!(write!(w, "    fn from_str(s: &str) -> Result<Self, Self::Err> {{\n"));
         try!(write!(w, "        match s {{\n"));
         for v in vec {
-            try!(write!(w, "            \"{}\" => Ok({}::{}),\n", v.s, name, v.s));
+            // an alias parses as the variant that it's an alias of
+            let variant = vec.iter().find(|o| o.value == v.value).unwrap();
+            try!(write!(w, "            \"{}\" => Ok({}::{}),\n", string_name(v, file_args), name,
+                        variant.ident()));
         }
-        try!(write!(w, "            _ => Err( () )\n"));
+        try!(write!(w, "            _ => Err({}(String::from(s)))\n", error));
         try!(write!(w, "        }}\n"));
         try!(write!(w, "    }}\n"));
         try!(write!(w, "}}\n"));
@@ -205,23 +885,118 @@ impl FormatOutput for FormatOutputFromStr {
     }
 }
 
+// What to do with a value that has more than one name: "error", "alias" or
+// "drop".
+fn duplicates_policy(file_args: &FileArgs) -> Result<&str> {
+    match file_args.duplicates.as_ref().map(|s| &s[..]) {
+        None => Ok("alias"),
+        Some(d @ "error") | Some(d @ "alias") | Some(d @ "drop") => Ok(d),
+        Some(d) => Err(Error::config(format!("duplicates should be error, alias or drop, \
+                                             not {}", d))),
+    }
+}
+
+// Split vec, which is in input order, into the first name for each value,
+// which become enum variants, and the rest, which become aliases unless
+// file_args says to drop them.
+fn split_duplicates(vec: &Vec<Variant>,
+                    file_args: &FileArgs) -> Result<(Vec<Variant>, Vec<Variant>)> {
+    let policy = try!(duplicates_policy(file_args));
+    let mut unique: Vec<Variant> = Vec::new();
+    let mut aliases: Vec<Variant> = Vec::new();
+    for v in vec {
+        if let Some(first) = unique.iter().find(|u| u.value == v.value) {
+            if policy == "error" {
+                return Err(Error::Generate(format!("{} has the same value as {} ({})",
+                                                   v.name, first.name, v.value)))
+            }
+            debug!("{} is an alias of {}", v.name, first.name);
+            if policy == "alias" {
+                aliases.push(v.clone());
+            }
+            continue;
+        }
+        unique.push(v.clone());
+    }
+    Ok((unique, aliases))
+}
+
+// Put the variants in the order that file_args asks for.
+fn order_variants(mut vec: Vec<Variant>, file_args: &FileArgs) -> Result<Vec<Variant>> {
+    match file_args.order.as_ref().map(|s| &s[..]) {
+        Some("source") => (),
+        None | Some("value") => vec.sort_by_key(|v| v.value),
+        Some("name") => vec.sort_by(|a, b| a.name.cmp(&b.name)),
+        Some(o) => return Err(Error::config(format!("order should be source, value or name, \
+                                                    not {}", o))),
+    }
+    Ok(vec)
+}
+
+// An associated const for every name after the first for a value.
+struct FormatOutputAliases;
+impl FormatOutput for FormatOutputAliases {
+    fn write(&self, w: &mut Write, name: &String, _: &FileArgs, vec: &Vec<Variant>) -> Result<()> {
+        let mut aliases: Vec<(&Variant, &Variant)> = Vec::new();
+        for (i, v) in vec.iter().enumerate() {
+            if let Some(first) = vec[..i].iter().find(|o| o.value == v.value) {
+                aliases.push((v, first));
+            }
+        }
+        if aliases.is_empty() {
+            return Ok(())
+        }
+        try!(write!(w, "#[allow(dead_code, non_upper_case_globals)]\n"));
+        try!(write!(w, "impl {} {{\n", name));
+        for &(alias, first) in &aliases {
+            try!(write!(w, "    pub const {}: {} = {}::{};\n", alias.ident(), name, name,
+                        first.ident()));
+        }
+        try!(write!(w, "}}\n"));
+        Ok(())
+    }
+}
+
 struct FormatOutputEnum;
 impl FormatOutputEnum {
-    fn write(&self, w: &mut Write, name: &String, derive: Option<&String>, hex: bool, vec: &Vec<CEnum>) -> Result<()> {
-        try!(write!(w, "#[allow(dead_code, non_camel_case_types)]\n"));
-        match derive
-        {
-            Some(s) => try!(write!(w, "#[derive({})]\n", s)),
-            None => (),
+    fn write(&self, w: &mut Write, name: &String, file_args: &FileArgs, vec: &Vec<Variant>) -> Result<()> {
+        match file_args.case.as_ref().map(|s| &s[..]) {
+            Some("UpperCamelCase") => try!(write!(w, "#[allow(dead_code)]\n")),
+            _ => try!(write!(w, "#[allow(dead_code, non_camel_case_types)]\n")),
         }
+        let default = match file_args.default && file_args.default_attr {
+            true => Some(try!(default_variant(vec, name, file_args))),
+            false => None,
+        };
+        let mut derive: Vec<&str> = match file_args.derive {
+            Some(ref s) => s.split(',').map(|d| d.trim()).filter(|d| !d.is_empty()).collect(),
+            None => Vec::new(),
+        };
+        if default.is_some() && !derive.contains(&"Default") {
+            derive.push("Default");
+        }
+        if !derive.is_empty() {
+            try!(write!(w, "#[derive({})]\n", derive.join(", ")));
+        }
+        let repr = try!(choose_repr(vec, file_args.repr.as_ref()));
+        // The discriminants of a #[repr(C)] enum are isize, so negative values
+        // can't be cast from an unsigned type.
+        let c = file_args.repr.as_ref().map(|r| &r[..]) == Some("C");
+        try!(write!(w, "#[repr({})]\n", if c { "C" } else { repr }));
         try!(write!(w, "pub enum {} {{\n", name));
 
         for v in vec {
-            if hex {
-                try!(write!(w, "    {} = 0x{:X},\n", v.s, v.i));
+            if default.map(|d| d.name == v.name) == Some(true) {
+                try!(write!(w, "    #[default]\n"));
+            }
+            if file_args.hex == Some(true) && c {
+                try!(write!(w, "    {} = {},\n", v.ident(), hex_pattern(v.value)));
+            }
+            else if file_args.hex == Some(true) {
+                try!(write!(w, "    {} = {},\n", v.ident(), hex_discriminant(v.value, repr)));
             }
             else {
-                try!(write!(w, "    {} = {},\n", v.s, v.i));
+                try!(write!(w, "    {} = {},\n", v.ident(), v.value));
             }
         }
 
@@ -240,9 +1015,8 @@ macro_rules! get_key_string {
             let $v = $t.get(stringify!($v)).unwrap();
             let $v = $v.as_str();
             if $v.is_none() {
-                return Err(Error::new(ErrorKind::Other,
-                                      format!("{} wasn't available as str",
-                                              stringify!($v))))
+                return Err(Error::config(format!("{} wasn't available as str",
+                                                 stringify!($v))))
             }
             let $v = $v.unwrap();
             $a.$v = Some(String::from($v));
@@ -257,135 +1031,619 @@ macro_rules! get_key_bool {
             let $v = $t.get(stringify!($v)).unwrap();
             let $v = $v.as_bool();
             if $v.is_none() {
-                return Err(Error::new(ErrorKind::Other,
-                                      format!("{} wasn't available as bool",
-                                              stringify!($v))))
+                return Err(Error::config(format!("{} wasn't available as bool",
+                                                 stringify!($v))))
             }
-            $a.$v = $v.unwrap();
+            // From so that Option<bool> options can use this too
+            $a.$v = From::from($v.unwrap());
         }
     }
 }
 
 fn parse_toml(path: &PathBuf) -> Result<FileArgs>
 {
-    let mut fa = FileArgs::default();
-    let mut f = try!(File::open(&path));
+    let mut f = try!(File::open(&path).map_err(|e| Error::io(e, path)));
 
     let mut s = String::new();
-    try!(f.read_to_string(&mut s));
-    let table = toml::Parser::new(&s).parse();
-    if table.is_none() {
-        return Err(Error::new(ErrorKind::Other,
-                              format!("failed to parse {}", path.display())))
-    }
-    let table = table.unwrap();
-
-    let rust_enum_derive = table.get("rust-enum-derive");
-    if rust_enum_derive.is_none() {
-        return Err(Error::new(ErrorKind::Other,
-                              format!("couldn't find a rust-enum-derive table in {}",
-                                      path.display())))
-    }
-    let rust_enum_derive = rust_enum_derive.unwrap();
-    let rust_enum_derive = rust_enum_derive.as_table();
-    if rust_enum_derive.is_none() {
-        return Err(Error::new(ErrorKind::Other,
-                              format!("rust-enum-derive wasn't a table")))
-    }
-    let rust_enum_derive = rust_enum_derive.unwrap();
-
-    get_key_string!(rust_enum_derive, fa, name);
-    get_key_string!(rust_enum_derive, fa, derive);
-    get_key_bool!(rust_enum_derive, fa, define);
-    get_key_bool!(rust_enum_derive, fa, default);
-    get_key_bool!(rust_enum_derive, fa, display);
-    get_key_bool!(rust_enum_derive, fa, fromstr);
-    get_key_bool!(rust_enum_derive, fa, fromprimative);
-    get_key_bool!(rust_enum_derive, fa, hex);
-    get_key_bool!(rust_enum_derive, fa, pretty_fmt);
+    try!(f.read_to_string(&mut s).map_err(|e| Error::io(e, path)));
+    let mut parser = toml::Parser::new(&s);
+    let table = match parser.parse() {
+        Some(t) => t,
+        None => {
+            let source: Option<Box<::std::error::Error + Send + Sync>> =
+                match parser.errors.into_iter().next() {
+                    Some(e) => Some(Box::new(e)),
+                    None => None,
+                };
+            return Err(Error::Config { path: Some(path.clone()),
+                                       msg: String::from("failed to parse the TOML"),
+                                       source: source })
+        }
+    };
+    parse_toml_table(&table).map_err(|e| e.with_path(path))
+}
+
+fn parse_toml_table(table: &toml::Table) -> Result<FileArgs>
+{
+    let rust_enum_derive = match table.get("rust-enum-derive") {
+        Some(t) => t,
+        None => return Err(Error::config(format!("couldn't find a rust-enum-derive table"))),
+    };
+    let rust_enum_derive = match rust_enum_derive.as_table() {
+        Some(t) => t,
+        None => return Err(Error::config(format!("rust-enum-derive wasn't a table"))),
+    };
+    let fa = try!(FileArgs::from_toml_table(rust_enum_derive));
     debug!("fa = {:?}", fa);
 
     Ok(fa)
 }
 
-fn get_num(s: &str) -> i32 {
-    use std::str::FromStr;
-    use regex::Regex;
-    let re_int = Regex::new(r"^(0x)?([:digit:]+)$").unwrap();
-    let re_shift = Regex::new(r"^([:digit:]+)[:space:]*<<[:space:]*([:digit:]+)$").unwrap();
-
-    if re_int.is_match(s) {
-        let caps = re_int.captures(s).unwrap();
-        let radix: u32 = match caps.at(1) {
-            Some(_) => 16,
-            None => 10,
+impl FileArgs {
+    /// Read the options from a table with the same keys as the
+    /// `[rust-enum-derive]` table of a .toml file. Keys that aren't options
+    /// are ignored.
+    pub fn from_toml_table(rust_enum_derive: &toml::Table) -> Result<FileArgs>
+    {
+        let mut fa = FileArgs::default();
+        get_key_string!(rust_enum_derive, fa, name);
+        get_key_string!(rust_enum_derive, fa, derive);
+        get_key_string!(rust_enum_derive, fa, repr);
+        get_key_bool!(rust_enum_derive, fa, define);
+        match rust_enum_derive.get("default") {
+            None => (),
+            Some(&toml::Value::Boolean(b)) => fa.default = b,
+            Some(&toml::Value::String(ref s)) => {
+                fa.default = true;
+                fa.default_value = Some(s.clone());
+            }
+            Some(&toml::Value::Integer(i)) => {
+                fa.default = true;
+                fa.default_value = Some(i.to_string());
+            }
+            Some(_) => return Err(Error::config(String::from("default wasn't available as \
+                                                              bool, str or integer"))),
+        }
+        get_key_bool!(rust_enum_derive, fa, default_attr);
+        get_key_bool!(rust_enum_derive, fa, display);
+        get_key_bool!(rust_enum_derive, fa, fromstr);
+        get_key_bool!(rust_enum_derive, fa, fromprimative);
+        get_key_bool!(rust_enum_derive, fa, tryfrom);
+        get_key_bool!(rust_enum_derive, fa, raw);
+        get_key_bool!(rust_enum_derive, fa, hex);
+        get_key_bool!(rust_enum_derive, fa, pretty_fmt);
+        get_key_bool!(rust_enum_derive, fa, auto);
+        get_key_bool!(rust_enum_derive, fa, serialize);
+        get_key_bool!(rust_enum_derive, fa, deserialize);
+        get_key_string!(rust_enum_derive, fa, serde_repr);
+        get_key_string!(rust_enum_derive, fa, duplicates);
+        get_key_string!(rust_enum_derive, fa, order);
+        match rust_enum_derive.get("strip_prefix") {
+            None | Some(&toml::Value::Boolean(false)) => (),
+            Some(&toml::Value::String(ref s)) => fa.strip_prefix = Some(s.clone()),
+            Some(_) => return Err(Error::config(String::from("strip_prefix should be \"auto\", \
+                                                              a prefix or false"))),
+        }
+        get_key_string!(rust_enum_derive, fa, case);
+        get_key_string!(rust_enum_derive, fa, display_names);
+        get_key_bool!(rust_enum_derive, fa, flags);
+        if let Some(enums) = rust_enum_derive.get("enums") {
+            let enums = match enums.as_slice() {
+                Some(a) => a,
+                None => return Err(Error::config(format!("enums wasn't available as array"))),
+            };
+            let mut v = Vec::new();
+            for e in enums {
+                match e.as_str() {
+                    Some(s) => v.push(String::from(s)),
+                    None => return Err(Error::config(format!("enums wasn't an array of str"))),
+                }
+            }
+            fa.enums = Some(v);
+        }
+        fa.rename = try!(get_string_table(rust_enum_derive, "rename"));
+        fa.serde_rename = try!(get_string_table(rust_enum_derive, "serde_rename"));
+        Ok(fa)
+    }
+}
+
+// A table of strings, like rename. Empty if key isn't there.
+fn get_string_table(table: &toml::Table, key: &str) -> Result<HashMap<String, String>> {
+    let mut map = HashMap::new();
+    if let Some(t) = table.get(key) {
+        let t = match t.as_table() {
+            Some(t) => t,
+            None => return Err(Error::config(format!("{} wasn't a table", key))),
         };
-        let digits = caps.at(2).unwrap();
-        i32::from_str_radix(digits, radix).unwrap()
+        for (k, v) in t {
+            match v.as_str() {
+                Some(s) => { map.insert(k.clone(), String::from(s)); }
+                None => return Err(Error::config(format!("{}.{} wasn't available as str", key, k))),
+            }
+        }
     }
-    else if re_shift.is_match(s) {
-        let caps = re_shift.captures(s).unwrap();
-        let l: i32 = FromStr::from_str(caps.at(1).unwrap()).unwrap();
-        let r: i32 = FromStr::from_str(caps.at(2).unwrap()).unwrap();
-        l<<r
+    Ok(map)
+}
+
+// Where a line of input came from, for error messages.
+struct Location<'a> {
+    path: Option<&'a PathBuf>,
+    input: &'a str,
+    line: usize,
+    text: &'a str,
+}
+impl<'a> Location<'a> {
+    fn error(&self, column: usize, len: usize, msg: String) -> ParseError {
+        ParseError { path: self.path.cloned(), input: String::from(self.input),
+                     line: self.line, column: column + 1,
+                     len: len, text: String::from(self.text), msg: msg }
     }
-    else {
-        panic!("couldn't parse '{}' as int", s)
+
+    fn span(&self, column: usize, len: usize) -> Span {
+        Span { line: self.line, column: column + 1, len: len }
     }
 }
 
-/// Return a sorted Vec of CEnum structs
-fn parse_buff<T: BufRead>(read: T, parse_enum: bool) -> Vec<CEnum> {
-    use regex::Regex;
-    let re = match parse_enum {
-        true => Regex::new(r"^[:space:]*([[:alnum:]_]+)([:space:]*=[:space:]*([:graph:]+))?[:space:]*,").unwrap(),
-        false => Regex::new(r"^#define[:space:]+([:graph:]+)[:space:]+([:graph:]+)").unwrap(),
-    };
-    let mut v: Vec<CEnum> = Vec::new();
-
-    let mut num: i32 = 0;
-    for line in read.lines() {
-        let s = line.unwrap();
-        for cap in re.captures_iter(&s) {
-            let i: i32 = match parse_enum {
-                true => match cap.at(3) {
-                    Some(s) => get_num(s),
-                    None => num,
-                },
-                false => get_num(cap.at(2).unwrap()),
-            };
-            num = i + 1;
-            v.push(CEnum::new(i, cap.at(1).unwrap()));
-        }
+// Evaluate s, which starts at byte offset start of the line at loc.
+fn get_num(s: &str, start: usize, loc: &Location,
+           symbols: &SymbolTable) -> Result<i128> {
+    match expr::eval(s, symbols) {
+        Ok(i) => Ok(i),
+        Err(e) => Err(Error::Eval(loc.error(start + e.pos, e.len, e.msg))),
     }
-
-    v.sort();
-    v
 }
 
-fn get_input(file_path: Option<&PathBuf>, file_args: &FileArgs) -> Vec<CEnum> {
-    match file_path {
-        Some(ref s) => {
-            // remove this unwrap as soon as expect is stabalized
-            let f = File::open(s).unwrap();
-            let r = BufReader::new(f);
-            parse_buff(r, !file_args.define)
+// Blank out C comments so that they don't end up in values. in_comment
+// carries an unterminated /* comment over to the next line. Comments are
+// replaced with spaces so that offsets into the line stay the same, and their
+// text is added to comment.
+fn strip_comments(s: &str, in_comment: &mut bool, comment: &mut String) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if *in_comment {
+            if c == '*' && chars.peek() == Some(&'/') {
+                chars.next();
+                out.push(' ');
+                *in_comment = false;
+            }
+            else {
+                comment.push(c);
+            }
+            for _ in 0..c.len_utf8() {
+                out.push(' ');
+            }
         }
-        None => {
-            let r = BufReader::new(std::io::stdin());
-            parse_buff(r, !file_args.define)
+        else if c == '/' && chars.peek() == Some(&'*') {
+            chars.next();
+            out.push_str("  ");
+            *in_comment = true;
+        }
+        else if c == '/' && chars.peek() == Some(&'/') {
+            chars.next();
+            comment.extend(chars);
+            break;
+        }
+        else {
+            out.push(c);
         }
     }
+    out
 }
 
-fn write_factory(file_path: Option<&PathBuf>) -> Result<Box<Write>> {
+// Tidy up the text of a comment, dropping the leading * of the lines of a
+// block comment. Returns None if nothing is left.
+fn clean_comment(s: &str) -> Option<String> {
+    let s = s.trim().trim_matches('*').trim();
+    match s.is_empty() {
+        true => None,
+        false => Some(String::from(s)),
+    }
+}
+
+/// Convert a C enum tag or typedef name to a rust type name, for example
+/// net_device_flags becomes NetDeviceFlags. A trailing _t is dropped.
+fn to_camel_case(s: &str) -> String {
+    let s = if s.ends_with("_t") { &s[..s.len() - 2] } else { s };
+    camel_case(s, true)
+}
+
+// Join the _ separated words of s in camel case, with the first letter upper
+// case if upper.
+fn camel_case(s: &str, upper: bool) -> String {
+    let mut out = String::new();
+    for part in s.split('_').filter(|p| !p.is_empty()) {
+        // Only lower case the rest of all upper case words, so that a name
+        // that is already camel case is left alone.
+        let lower = part.chars().all(|c| !c.is_lowercase());
+        let mut chars = part.chars();
+        let first = chars.next().unwrap();
+        if upper || !out.is_empty() {
+            out.extend(first.to_uppercase());
+        }
+        else {
+            out.extend(first.to_lowercase());
+        }
+        for c in chars {
+            if lower { out.extend(c.to_lowercase()); } else { out.push(c); }
+        }
+    }
+    out
+}
+
+// Convert a C name to one of the cases that the case option allows.
+fn convert_case(s: &str, case: &str) -> Result<String> {
+    let words = s.split('_').filter(|p| !p.is_empty());
+    match case {
+        "UpperCamelCase" => Ok(camel_case(s, true)),
+        "lowerCamelCase" => Ok(camel_case(s, false)),
+        "snake_case" => Ok(words.map(|w| w.to_lowercase()).collect::<Vec<_>>().join("_")),
+        "SCREAMING_SNAKE_CASE" => Ok(words.map(|w| w.to_uppercase()).collect::<Vec<_>>().join("_")),
+        _ => Err(Error::config(format!("case should be UpperCamelCase, lowerCamelCase, \
+                                       snake_case or SCREAMING_SNAKE_CASE, not {}", case))),
+    }
+}
+
+// Work out the rust name of every variant that doesn't have one yet: strip
+// the prefix, convert the case, and put a _ in front of a name that would
+// start with a digit.
+fn name_variants(vec: &mut Vec<Variant>, file_args: &FileArgs) -> Result<()> {
+    match file_args.display_names.as_ref().map(|s| &s[..]) {
+        None | Some("c") | Some("rust") => (),
+        Some(n) => return Err(Error::config(format!("display_names should be c or rust, \
+                                                    not {}", n))),
+    }
+    let prefix = match file_args.strip_prefix.as_ref().map(|s| &s[..]) {
+        None => None,
+        Some("auto") => common_prefix(vec),
+        Some(p) => Some(String::from(p)),
+    };
+    for v in vec.iter_mut().filter(|v| v.ident.is_none()) {
+        let mut ident = match prefix {
+            Some(ref p) if v.name.starts_with(&p[..]) => String::from(&v.name[p.len()..]),
+            _ => v.name.clone(),
+        };
+        if let Some(ref case) = file_args.case {
+            ident = try!(convert_case(&ident, case));
+        }
+        if ident.is_empty() {
+            return Err(Error::Generate(match prefix {
+                Some(ref p) => format!("{} has no name left once {} is stripped", v.name, p),
+                None => format!("{} has no name left once its case is converted", v.name),
+            }))
+        }
+        if ident.starts_with(|c: char| c.is_ascii_digit()) {
+            ident.insert(0, '_');
+        }
+        if KEYWORDS.contains(&&ident[..]) {
+            // these are the keywords that can't be raw identifiers
+            if ["crate", "self", "Self", "super", "_"].contains(&&ident[..]) {
+                return Err(Error::Generate(format!("{} would be named {}, which is a keyword",
+                                                   v.name, ident)))
+            }
+            ident.insert_str(0, "r#");
+        }
+        v.ident = Some(ident);
+    }
+    for (i, v) in vec.iter().enumerate() {
+        if let Some(o) = vec[..i].iter().find(|o| o.ident() == v.ident()) {
+            return Err(Error::Generate(format!("{} and {} would both be named {}",
+                                               o.name, v.name, v.ident())))
+        }
+    }
+    Ok(())
+}
+
+// The rust keywords, strict and reserved, as of the 2024 edition.
+const KEYWORDS: [&'static str; 53] = [
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue",
+    "crate", "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen",
+    "if", "impl", "in", "let", "loop", "macro", "match", "mod", "move", "mut", "override",
+    "priv", "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait",
+    "true", "try", "type", "typeof", "unsafe", "unsized", "use", "virtual", "where",
+    "while", "yield", "_",
+];
+
+// The name of a variant for Display, FromStr, pretty_fmt() and serde.
+fn string_name<'a>(v: &'a Variant, file_args: &FileArgs) -> &'a str {
+    match file_args.display_names.as_ref().map(|s| &s[..]) {
+        Some("rust") => v.ident().trim_left_matches("r#"),
+        _ => &v.name,
+    }
+}
+
+// The longest prefix made of whole _ separated words that every name starts
+// with, including the last _, that leaves at least one word of every name.
+// For example RTM_NEWLINK and RTM_DELLINK have the prefix RTM_.
+fn common_prefix(vec: &Vec<Variant>) -> Option<String> {
+    let mut words: Vec<&str> = match vec.first() {
+        Some(v) => v.name.split('_').collect(),
+        None => return None,
+    };
+    for v in vec {
+        let w: Vec<&str> = v.name.split('_').collect();
+        // the last word of a name can't be part of the prefix
+        let shared = words.iter().zip(w[..w.len() - 1].iter())
+                          .take_while(|&(a, b)| a == b).count();
+        words.truncate(shared);
+    }
+    match words.iter().all(|w| w.is_empty()) {
+        true => None,
+        false => Some(words.iter().map(|w| format!("{}_", w)).collect()),
+    }
+}
+
+// The part of a C name before the first _, which groups related #defines
+// together. For example NETLINK_ROUTE has the prefix NETLINK.
+fn name_prefix(s: &str) -> Option<&str> {
+    match s.find('_') {
+        Some(i) if i > 0 => Some(&s[..i]),
+        _ => None,
+    }
+}
+
+// The name of an anonymous enum: the longest prefix shared by every value,
+// or failing that the first word they all share. IF_OPER_UP and IF_OPER_DOWN
+// give IF_OPER, so that IF_LINK_MODE_DEFAULT in the next enum doesn't collide.
+// Values that start with _, like __IF_OPER_MAX, don't count.
+fn common_name_prefix(v: &Vec<Variant>) -> Option<String> {
+    let v: Vec<Variant> = v.iter().filter(|e| !e.name.starts_with('_')).cloned().collect();
+    if v.is_empty() {
+        return None;
+    }
+    if let Some(p) = common_prefix(&v) {
+        return Some(String::from(p.trim_right_matches('_')));
+    }
+    match name_prefix(&v[0].name) {
+        Some(p) if v.iter().all(|e| name_prefix(&e.name) == Some(p)) => Some(String::from(p)),
+        _ => None,
+    }
+}
+
+// Split s at the commas that aren't inside of parentheses. Returns the start
+// and end of each part, and whether it was ended by a comma.
+fn split_commas(s: &str) -> Vec<(usize, usize, bool)> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push((start, i, true));
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push((start, s.len(), false));
+    parts
+}
+
+/// Split the input into blocks, one per enum, and return them and their
+/// values in the order that they were found. Every value parsed
+/// is added to symbols so that later values can refer to it by name. path and
+/// input, what the input is called, are only used for error messages.
+///
+/// When parsing enums both bare lists of values and whole
+/// `enum name { ... };` or `typedef enum { ... } name_t;` blocks are
+/// understood. A typedef name wins over the enum tag, and an anonymous enum
+/// is named after the prefix its values share. A line can hold more than one
+/// value, or a whole enum. When parsing #defines each
+/// group of #defines with the same prefix (NETLINK_ROUTE, NETLINK_UNUSED, ...)
+/// becomes a block named after that prefix, even when other #defines come
+/// between them. A #define that can't be evaluated is skipped, unless its
+/// block is one of the wanted ones.
+fn parse_buff<T: BufRead>(read: T, parse_enum: bool, path: Option<&PathBuf>, input: &str,
+                          wanted: &[String], symbols: &mut SymbolTable) -> Result<Vec<EnumDef>> {
+    use regex::Regex;
+    // An enum value is matched against one comma separated part of a line.
+    let re = match parse_enum {
+        true => Regex::new(r"^[:space:]*([[:alpha:]_][[:alnum:]_]*)([:space:]*=[:space:]*(.+?))?[:space:]*$").unwrap(),
+        false => Regex::new(r"^#define[:space:]+([[:alnum:]_]+)[:space:]+(.+?)[:space:]*$").unwrap(),
+    };
+    let re_start = Regex::new(r"^[:space:]*(typedef[:space:]+)?enum([:space:]+([[:alpha:]_][[:alnum:]_]*))?[:space:]*(\{|$)").unwrap();
+    let re_end = Regex::new(r"\}[:space:]*([[:alpha:]_][[:alnum:]_]*)?[:space:]*;").unwrap();
+    let mut blocks: Vec<EnumDef> = Vec::new();
+    // the block that values are currently being added to
+    let mut block: Option<EnumDef> = None;
+
+    let mut num: i128 = 0;
+    let mut in_comment = false;
+    let mut in_block = false;
+    // comment lines that the next enum block or value might belong to
+    let mut pending: Vec<String> = Vec::new();
+    for (line_no, line) in read.lines().enumerate() {
+        let line = try!(line);
+        let loc = Location { path: path, input: input, line: line_no + 1, text: &line };
+        let mut comment = String::new();
+        let s = strip_comments(&line, &mut in_comment, &mut comment);
+        let comment = clean_comment(&comment);
+        if s.trim().is_empty() {
+            match comment {
+                Some(c) => pending.push(c),
+                None => pending.clear(),
+            }
+            continue;
+        }
+        let mut leading = match pending.is_empty() {
+            true => None,
+            false => Some(pending.join("\n")),
+        };
+        pending.clear();
+
+        // The values on the line, as the positions in s of their names and
+        // their values (if they have one).
+        let mut values: Vec<((usize, usize), Option<(usize, usize)>)> = Vec::new();
+        // Some(typedef name) if a block is closed on this line
+        let mut closed: Option<Option<String>> = None;
+        if parse_enum {
+            let mut from = 0;
+            if let Some(cap) = re_start.captures(&s) {
+                if let Some(b) = block.take() {
+                    blocks.push(b);
+                }
+                from = cap.pos(0).unwrap().1;
+                let mut b = EnumDef::new(cap.at(3).map(String::from));
+                let start = s.len() - s.trim_left().len();
+                b.span = loc.span(start, s[start..from].trim_right().len());
+                b.comment = leading.take();
+                block = Some(b);
+                in_block = true;
+                num = 0;
+            }
+            let mut to = s.len();
+            if in_block {
+                if let Some(cap) = re_end.captures(&s[from..]) {
+                    to = from + cap.pos(0).unwrap().0;
+                    closed = Some(cap.at(1).map(String::from));
+                }
+            }
+            // Inside of a block the last value doesn't need a trailing comma.
+            for (start, end, comma) in split_commas(&s[from..to]) {
+                let (start, end) = (from + start, from + end);
+                let part = &s[start..end];
+                match re.captures(part) {
+                    Some(ref cap) if comma || in_block => {
+                        let at = |(a, b): (usize, usize)| (start + a, start + b);
+                        values.push((at(cap.pos(1).unwrap()), cap.pos(3).map(at)));
+                    }
+                    _ if part.trim().is_empty() => {}
+                    // Inside of an enum block a part that starts with a name
+                    // has to be a value. Anything else, and everything outside
+                    // of a block that isn't a value (other C code), is dropped.
+                    _ if in_block => {
+                        let trimmed = part.trim_left();
+                        if trimmed.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+                            let msg = String::from("expected a value like NAME, or NAME = VALUE,");
+                            let column = end - trimmed.len();
+                            let len = trimmed.trim_right().len() + if comma { 1 } else { 0 };
+                            return Err(Error::Parse(loc.error(column, len, msg)))
+                        }
+                    }
+                    _ => break,
+                }
+            }
+        }
+        else if let Some(cap) = re.captures(&s) {
+            values.push((cap.pos(1).unwrap(), cap.pos(2)));
+        }
+
+        let mut comment = comment.or(leading);
+        for ((start, end), value) in values {
+            let variant = &s[start..end];
+            let i: i128 = match value {
+                Some((vs, ve)) => match get_num(&s[vs..ve], vs, &loc, symbols) {
+                    Ok(i) => i,
+                    // A #define that isn't a constant, like a cast, is skipped
+                    // unless its block was asked for by name.
+                    Err(e) => {
+                        let prefix = name_prefix(variant);
+                        if parse_enum || wanted.iter().any(|w| Some(&w[..]) == prefix) {
+                            return Err(e)
+                        }
+                        debug!("skipping #define {}: {}", variant, e);
+                        continue
+                    }
+                },
+                None => num,
+            };
+            let expr = value.map(|(vs, ve)| String::from(s[vs..ve].trim()));
+            num = i + 1;
+            symbols.insert(String::from(variant), i);
+            let v = Variant { name: String::from(variant), value: i,
+                              span: loc.span(start, end - start),
+                              comment: comment.take(), expr: expr, ident: None };
+
+            // Bare enum values are all collected into one block, #defines are
+            // split up whenever their prefix changes.
+            let prefix = name_prefix(variant).map(String::from);
+            let new_block = match block {
+                None => true,
+                Some(ref b) => !parse_enum && b.name != prefix,
+            };
+            if new_block {
+                if let Some(b) = block.take() {
+                    blocks.push(b);
+                }
+                let mut b = EnumDef::new(if parse_enum { None } else { prefix });
+                b.span = v.span.clone();
+                block = Some(b);
+            }
+            block.as_mut().unwrap().variants.push(v);
+        }
+
+        if let Some(typedef) = closed {
+            let mut b = block.take().unwrap();
+            if typedef.is_some() {
+                b.name = typedef;
+            }
+            if b.name.is_none() {
+                b.name = common_name_prefix(&b.variants);
+            }
+            blocks.push(b);
+            in_block = false;
+        }
+    }
+    if let Some(mut b) = block.take() {
+        // an enum block that was never closed
+        if in_block && b.name.is_none() {
+            b.name = common_name_prefix(&b.variants);
+        }
+        blocks.push(b);
+    }
+    if !parse_enum {
+        let mut merged: Vec<EnumDef> = Vec::new();
+        for b in blocks {
+            match merged.iter().position(|m| m.name == b.name) {
+                Some(i) => merged[i].variants.extend(b.variants),
+                None => merged.push(b),
+            }
+        }
+        blocks = merged;
+    }
+
+    blocks.retain(|b| !b.variants.is_empty());
+    for b in blocks.iter() {
+        if let Some(prefix) = b.common_prefix() {
+            debug!("the values of {} share the prefix {}",
+                   b.name.as_ref().map(|n| &n[..]).unwrap_or("the enum"), prefix);
+        }
+        if b.is_flags() {
+            info!("{} looks like a set of flags",
+                  b.name.as_ref().map(|n| &n[..]).unwrap_or("the enum"));
+        }
+    }
+    Ok(blocks)
+}
+
+fn get_input(file_path: Option<&PathBuf>, file_args: &FileArgs,
+             symbols: &mut SymbolTable) -> Result<Vec<EnumDef>> {
+    let wanted = file_args.enums.as_ref().map(|e| &e[..]).unwrap_or(&[]);
+    match file_path {
+        Some(ref s) => {
+            let f = try!(File::open(s).map_err(|e| Error::io(e, s)));
+            let r = BufReader::new(f);
+            parse_buff(r, !file_args.define, file_path, &s.display().to_string(), wanted, symbols)
+        }
+        None => {
+            let r = BufReader::new(std::io::stdin());
+            parse_buff(r, !file_args.define, file_path, "<stdin>", wanted, symbols)
+        }
+    }
+}
+
+fn write_factory(file_path: Option<&PathBuf>) -> Result<Box<Write>> {
     match file_path {
         Some(s) => {
-            try!(std::fs::create_dir_all(s.parent().unwrap()));
+            let parent = s.parent().unwrap().to_path_buf();
+            try!(std::fs::create_dir_all(&parent).map_err(|e| Error::io(e, &parent)));
             let f = try!(OpenOptions::new().write(true)
                                            .create(true)
                                            .truncate(true)
-                                           .open(s));
+                                           .open(s)
+                                           .map_err(|e| Error::io(e, s)));
             let w = BufWriter::new(f);
             Ok(Box::new(w))
         }
@@ -403,63 +1661,264 @@ fn write_factory(file_path: Option<&PathBuf>) -> Result<Box<Write>> {
 /// * `file_argsfile_args` - The arguments for how to process the input
 pub fn process(file_path_in: Option<&PathBuf>, file_path_out: Option<&PathBuf>,
                file_args: &FileArgs) -> Result<()> {
+    process_impl(file_path_in, file_path_out, file_args, &mut SymbolTable::new())
+}
+
+fn process_impl(file_path_in: Option<&PathBuf>, file_path_out: Option<&PathBuf>,
+                file_args: &FileArgs, symbols: &mut SymbolTable) -> Result<()> {
+    let blocks = try!(get_input(file_path_in, &file_args, symbols));
+    let input = match file_path_in {
+        Some(pb) => pb.to_string_lossy().into_owned(),
+        None => String::from("standard in"),
+    };
+    // Work out everything before opening the output so that an error doesn't
+    // leave a half written file behind.
+    let named = try!(name_blocks(blocks, &input, file_args));
+    let mut w = try!(write_factory(file_path_out));
+    write_blocks(&mut w, &named)
+}
+
+/// Like process(), but reads from a string and returns the generated code.
+///
+/// * `input` - The text to parse
+/// * `file_args` - The arguments for how to process the input
+pub fn process_str(input: &str, file_args: &FileArgs) -> Result<String> {
+    use std::io::Cursor;
+    let mut out = Vec::new();
+    try!(process_read(Cursor::new(input.as_bytes()), &mut out, "the string", "<string>",
+                      file_args));
+    Ok(String::from_utf8(out).unwrap())
+}
+
+/// Like process(), but reads from and writes to anything.
+///
+/// * `read` - Where to read the input from
+/// * `write` - Where to write the generated code to
+/// * `file_args` - The arguments for how to process the input
+pub fn process_io<R: BufRead, W: Write>(read: R, mut write: W,
+                                        file_args: &FileArgs) -> Result<()> {
+    process_read(read, &mut write, "the input", "<input>", file_args)
+}
+
+// input is the name of the input for messages, label is what it's called in
+// parse errors.
+fn process_read<R: BufRead>(read: R, w: &mut Write, input: &str, label: &str,
+                            file_args: &FileArgs) -> Result<()> {
+    let wanted = file_args.enums.as_ref().map(|e| &e[..]).unwrap_or(&[]);
+    let blocks = try!(parse_buff(read, !file_args.define, None, label, wanted,
+                                 &mut SymbolTable::new()));
+    let named = try!(name_blocks(blocks, input, file_args));
+    write_blocks(w, &named)
+}
+
+// Pick the blocks that file_args asks for and name them. input is the name of
+// the input for error messages.
+fn name_blocks(mut blocks: Vec<EnumDef>, input: &str,
+               file_args: &FileArgs) -> Result<Vec<PreparedEnum>> {
+    if let Some(ref enums) = file_args.enums {
+        for e in enums {
+            if !blocks.iter().any(|b| b.name.as_ref() == Some(e)) {
+                return Err(Error::config(format!("couldn't find an enum named {}", e)))
+            }
+        }
+        blocks.retain(|b| match b.name {
+            Some(ref n) => enums.contains(n),
+            None => false,
+        });
+    }
+    if blocks.len() < 1 {
+        return Err(Error::NoInput(String::from(input)))
+    }
+
+    let mut names: Vec<String> = Vec::new();
+    let mut prepared = Vec::new();
+    for b in &blocks {
+        try!(choose_repr(&b.variants, file_args.repr.as_ref()));
+        let name = rust_name(b, file_args, blocks.len() == 1);
+        if names.contains(&name) {
+            return Err(Error::config(format!("more than one enum would be named {}, \
+                                             use rename to tell them apart", name)))
+        }
+        prepared.push(try!(prepare_enum(b, &name, file_args)));
+        names.push(name);
+    }
+    Ok(prepared)
+}
+
+fn write_blocks(w: &mut Write, prepared: &Vec<PreparedEnum>) -> Result<()> {
+    for (i, p) in prepared.iter().enumerate() {
+        if i > 0 {
+            try!(write!(w, "\n"));
+        }
+        try!(write_enum(w, p));
+    }
+    Ok(())
+}
+
+// The name of the generated enum: the rename for its C name, then the name
+// from file_args (only if use_name), then its C name in CamelCase.
+fn rust_name(def: &EnumDef, file_args: &FileArgs, use_name: bool) -> String {
+    let renamed = match def.name {
+        Some(ref n) => file_args.rename.get(n),
+        None => None,
+    };
+    match (renamed, file_args.name.as_ref(), def.name.as_ref()) {
+        (Some(s), _, _) => s.clone(),
+        (None, Some(s), _) if use_name => s.clone(),
+        (None, _, Some(s)) => to_camel_case(s),
+        (None, _, None) => String::from("Name"),
+    }
+}
+
+// An enum with its variants named, split and ordered, ready to be written.
+struct PreparedEnum {
+    name: String,
+    // file_args with the auto and default choices filled in
+    file_args: FileArgs,
+    // the variants of the enum
+    unique: Vec<Variant>,
+    // every name, for the traits that go from a name to a value, with the
+    // variants before their aliases
+    named: Vec<Variant>,
+}
+
+// Work out the variants of def and the arguments to write them with, so that
+// checking them and writing them use the same data.
+fn prepare_enum(def: &EnumDef, name: &String, file_args: &FileArgs) -> Result<PreparedEnum> {
+    let mut file_args = match file_args.auto && def.is_flags() {
+        true => {
+            debug!("using hex and pretty_fmt for {}", name);
+            FileArgs { hex: file_args.hex.or(Some(true)),
+                       pretty_fmt: file_args.pretty_fmt.or(Some(true)), ..file_args.clone() }
+        }
+        false => file_args.clone(),
+    };
+    let mut variants = def.variants.clone();
+    try!(name_variants(&mut variants, &file_args));
+    let (unique, aliases) = try!(split_duplicates(&variants, &file_args));
+    let unique = try!(order_variants(unique, &file_args));
+    let aliases = try!(order_variants(aliases, &file_args));
+    let named: Vec<Variant> = unique.iter().chain(aliases.iter()).cloned().collect();
+    // The default can be an alias, which only named knows about, so it's
+    // swapped for the name of its variant here.
+    if file_args.default && !file_args.flags {
+        let d = try!(default_variant(&named, name, &file_args)).name.clone();
+        file_args.default_value = Some(d);
+    }
+    Ok(PreparedEnum { name: name.clone(), file_args: file_args, unique: unique, named: named })
+}
+
+// Write the enum and all of the traits asked for in its file_args.
+fn write_enum(w: &mut Write, p: &PreparedEnum) -> Result<()> {
+    let (name, file_args, unique, named) = (&p.name, &p.file_args, &p.unique, &p.named);
+    if file_args.flags {
+        return FormatOutputFlags.write(w, name, file_args, named)
+    }
+
     let mut fov: Vec<Box<FormatOutput>> = Vec::new();
-    if file_args.fromstr { fov.push(Box::new(FormatOutputFromStr)); }
-    if file_args.default { fov.push(Box::new(FormatOutputDefault)); }
+    if file_args.default && !file_args.default_attr { fov.push(Box::new(FormatOutputDefault)); }
     if file_args.display { fov.push(Box::new(FormatOutputDisplay)); }
     if file_args.fromprimative { fov.push(Box::new(FormatOutputFromPrimative)); }
-    if file_args.pretty_fmt { fov.push(Box::new(FormatOutputPrettyFmt)); }
+    if file_args.tryfrom { fov.push(Box::new(FormatOutputTryFrom)); }
+    if file_args.raw { fov.push(Box::new(FormatOutputRaw)); }
+    if file_args.pretty_fmt == Some(true) { fov.push(Box::new(FormatOutputPrettyFmt)); }
+    if file_args.serialize { fov.push(Box::new(FormatOutputSerialize)); }
+    if file_args.deserialize { fov.push(Box::new(FormatOutputDeserialize)); }
 
-    let vi = get_input(file_path_in, &file_args);
-    if vi.len() < 1 {
-        let input = match file_path_in {
-            Some(pb) => pb.to_string_lossy().into_owned(),
-            None => String::from("standard in"),
-        };
-        return Err(Error::new(ErrorKind::Other,
-                              format!("couldn't parse any input from {}.",
-                                      input)))
+    try!(FormatOutputEnum.write(w, name, file_args, unique));
+    try!(FormatOutputAliases.write(w, name, file_args, named));
+    if file_args.fromstr {
+        try!(FormatOutputFromStr.write(w, name, file_args, named));
     }
+    for vw in &fov {
+        try!(vw.write(w, name, file_args, unique));
+    }
+    Ok(())
+}
 
-    let mut w = try!(write_factory(file_path_out));
-    let name = match file_args.name
-    {
-        Some(ref s) => s.clone(),
-        None => String::from("Name"),
+/// Parse every enum in s, and their values, in the order that they were
+/// found.
+pub fn parse_all(s: &str, options: &ParseOptions) -> Result<Vec<EnumDef>> {
+    use std::io::Cursor;
+    let wanted: Vec<String> = options.enum_name.iter().cloned().collect();
+    let input = match options.path {
+        Some(ref p) => p.display().to_string(),
+        None => String::from("<string>"),
     };
+    parse_buff(Cursor::new(s.as_bytes()), !options.define, options.path.as_ref(), &input,
+               &wanted, &mut SymbolTable::new())
+}
 
-    let derive = file_args.derive.as_ref();
-    try!(FormatOutputEnum.write(&mut w, &name, derive, file_args.hex, &vi));
-    for vw in fov {
-        try!(vw.write(&mut w, &name, file_args.hex, &vi));
+/// Parse the enum named options.enum_name in s, or the first enum if no name
+/// was given.
+pub fn parse(s: &str, options: &ParseOptions) -> Result<EnumDef> {
+    let blocks = try!(parse_all(s, options));
+    if let Some(ref name) = options.enum_name {
+        return match blocks.into_iter().find(|b| b.name.as_ref() == Some(name)) {
+            Some(b) => Ok(b),
+            None => Err(Error::config(format!("couldn't find an enum named {}", name))),
+        }
     }
+    match blocks.into_iter().next() {
+        Some(b) => Ok(b),
+        None => {
+            let input = match options.path {
+                Some(ref pb) => pb.to_string_lossy().into_owned(),
+                None => String::from("the string"),
+            };
+            Err(Error::NoInput(input))
+        }
+    }
+}
 
-    Ok(())
+/// Generate the rust code for def, with the traits asked for in file_args.
+/// file_args.enums and file_args.define aren't used.
+pub fn generate(def: &EnumDef, file_args: &FileArgs) -> Result<String> {
+    if def.variants.is_empty() {
+        return Err(Error::Generate(format!("{} has no values",
+                                           rust_name(def, file_args, true))))
+    }
+    let mut out = Vec::new();
+    let p = try!(prepare_enum(def, &rust_name(def, file_args, true), file_args));
+    try!(write_enum(&mut out, &p));
+    Ok(String::from_utf8(out).unwrap())
 }
 
+// found is called with the paths of the .toml, .in and .rs files before each
+// input is processed.
 fn traverse_dir_impl(base_input_dir: &PathBuf,
                      base_output_dir: &PathBuf,
-                     sub_dir: &PathBuf) -> Result<()> {
+                     sub_dir: &PathBuf,
+                     symbols: &mut SymbolTable,
+                     found: &mut FnMut(&PathBuf, &PathBuf, &PathBuf)) -> Result<()> {
     let mut dir = PathBuf::new();
     dir.push(base_input_dir);
     dir.push(sub_dir);
 
-    if !fs::metadata(&dir).unwrap().is_dir() {
-        return Err(Error::new(ErrorKind::Other,
-                              format!("{} is not a directory", dir.display())))
+    if !try!(fs::metadata(&dir).map_err(|e| Error::io(e, &dir))).is_dir() {
+        return Err(Error::Config { path: Some(dir), msg: String::from("not a directory"),
+                                   source: None })
     }
 
+    // Visit entries in a stable order, later files can refer to constants
+    // defined by earlier ones.
+    let mut entries = Vec::new();
+    for entry in try!(fs::read_dir(&dir).map_err(|e| Error::io(e, &dir))) {
+        entries.push(try!(entry));
+    }
+    entries.sort_by(|a, b| a.path().cmp(&b.path()));
+
     // TODO: revisit. This follows symlinks, is that what we want?
     // If no we could use fs::symlink_metadata() treats symbolic links as
     // files, or DirEntry::file_type() which returns a FileType which we could
     // use to tell if this was a symbolic link or not?
-    for entry in try!(fs::read_dir(dir)) {
-        let entry = try!(entry);
-        if fs::metadata(entry.path()).unwrap().is_dir() {
+    for entry in entries {
+        if try!(fs::metadata(entry.path()).map_err(|e| Error::io(e, &entry.path()))).is_dir() {
             let mut new_sub_dir = PathBuf::new();
             new_sub_dir.push(sub_dir);
             new_sub_dir.push(entry.file_name());
-            try!(traverse_dir_impl(base_input_dir, base_output_dir, &new_sub_dir));
+            try!(traverse_dir_impl(base_input_dir, base_output_dir, &new_sub_dir, symbols,
+                                   found));
         } else {
             let path = entry.path();
             if path.extension().is_some() {
@@ -467,9 +1926,6 @@ fn traverse_dir_impl(base_input_dir: &PathBuf,
                 let extension = extension.to_string_lossy();
                 let extension = extension.to_lowercase();
                 if extension == "toml" {
-                    let args = try!(parse_toml(&path));
-
-                    let path = entry.path();
                     let base = path.file_stem().unwrap();
 
                     let mut input_file_path = PathBuf::new();
@@ -484,30 +1940,37 @@ fn traverse_dir_impl(base_input_dir: &PathBuf,
                     output_file_path.push(base);
                     output_file_path.set_extension("rs");
 
-                    try!(process(Some(&input_file_path), Some(&output_file_path), &args));
+                    found(&path, &input_file_path, &output_file_path);
+                    let args = try!(parse_toml(&path));
+                    try!(process_impl(Some(&input_file_path), Some(&output_file_path),
+                                      &args, symbols));
                 }
             }
         }
-    } // for entry in try!(fs::read_dir(dir))
+    } // for entry in entries
 
     Ok(())
 }
 
 /// This is the function that you call to process a whole directory heirarcy full of files.
 ///
+/// Files are processed in path order, and values may refer to constants
+/// defined in any file processed before them.
+///
 /// * `input_dir` - The input path of the directory to read from
 /// * `output_dir` - The output path of the directory to write to
 /// * `file_argsfile_args` - The arguments for how to process the input
 pub fn traverse_dir(input_dir: &PathBuf, output_dir: &PathBuf) -> Result<()> {
-    traverse_dir_impl(input_dir, &output_dir, &PathBuf::new())
+    traverse_dir_impl(input_dir, &output_dir, &PathBuf::new(), &mut SymbolTable::new(),
+                      &mut |_, _, _| ())
 }
 
 #[test]
 fn test_CENum_order() {
-    let a = CEnum::new(0, "");
-    let b = CEnum::new(1, "");
-    let c = CEnum::new(2, "");
-    let d = CEnum::new(0, "");
+    let a = Variant::new("", 0);
+    let b = Variant::new("", 1);
+    let c = Variant::new("", 2);
+    let d = Variant::new("", 0);
     assert!(a < b);
     assert!(b < c);
     assert!(a < c);
@@ -528,13 +1991,16 @@ fn test_parse_buff() {
 
     let buff = Cursor::new(s.as_bytes());
 
-    let v = parse_buff(buff, false);
+    let b = parse_buff(buff, false, None, "<string>", &[], &mut SymbolTable::new()).unwrap();
+    assert!(b.len() == 1);
+    assert!(b[0].name == Some(String::from("NETLINK")));
+    let v = &b[0].variants;
 
-    assert!(v[0].i == 0); assert!(v[0].s == "NETLINK_ROUTE");
-    assert!(v[1].i == 1); assert!(v[1].s == "NETLINK_UNUSED");
-    assert!(v[2].i == 3); assert!(v[2].s == "NETLINK_FIREWALL");
-    assert!(v[3].i == 4); assert!(v[3].s == "NETLINK_SOCK_DIAG");
-    assert!(v[4].i == 16); assert!(v[4].s == "NETLINK_GENERIC");
+    assert!(v[0].value == 0); assert!(v[0].name == "NETLINK_ROUTE");
+    assert!(v[1].value == 1); assert!(v[1].name == "NETLINK_UNUSED");
+    assert!(v[2].value == 3); assert!(v[2].name == "NETLINK_FIREWALL");
+    assert!(v[3].value == 4); assert!(v[3].name == "NETLINK_SOCK_DIAG");
+    assert!(v[4].value == 16); assert!(v[4].name == "NETLINK_GENERIC");
 }
 
 #[test]
@@ -553,12 +2019,836 @@ fn test_parse_buff_enum() {
                  RTM_DELADDR,";
 
     let buff = Cursor::new(s.as_bytes());
-    let v = parse_buff(buff, true);
-
-    assert!(v[0].i == 16); assert!(v[0].s == "RTM_NEWLINK");
-    assert!(v[1].i == 17); assert!(v[1].s == "RTM_DELLINK");
-    assert!(v[2].i == 18); assert!(v[2].s == "RTM_GETLINK");
-    assert!(v[3].i == 19); assert!(v[3].s == "RTM_SETLINK");
-    assert!(v[4].i == 20); assert!(v[4].s == "RTM_NEWADDR");
-    assert!(v[5].i == 21); assert!(v[5].s == "RTM_DELADDR");
+    let b = parse_buff(buff, true, None, "<string>", &[], &mut SymbolTable::new()).unwrap();
+    let v = &b[0].variants;
+
+    assert!(v[0].value == 16); assert!(v[0].name == "RTM_NEWLINK");
+    assert!(v[1].value == 17); assert!(v[1].name == "RTM_DELLINK");
+    assert!(v[2].value == 18); assert!(v[2].name == "RTM_GETLINK");
+    assert!(v[3].value == 19); assert!(v[3].name == "RTM_SETLINK");
+    assert!(v[4].value == 20); assert!(v[4].name == "RTM_NEWADDR");
+    assert!(v[5].value == 21); assert!(v[5].name == "RTM_DELADDR");
+}
+
+#[test]
+fn test_parse_buff_expressions() {
+    use std::io::Cursor;
+    let s = "IFF_UP = 1<<0,  /* sysfs */\n\
+             IFF_VOLATILE = (1 << 3) | (1 << 4), // loopback | pointopoint\n\
+             IFF_ALL = ~0,\n\
+             #define IFF_MASK ((1 << 2) - 1) /* mask */\n\
+             #define IFF_HALF (0x100 / 2)";
+
+    let b = parse_buff(Cursor::new(s.as_bytes()), true, None, "<string>", &[], &mut SymbolTable::new()).unwrap();
+    let v = &b[0].variants;
+    assert!(v[0].value == 1); assert!(v[0].name == "IFF_UP");
+    assert!(v[1].value == 24); assert!(v[1].name == "IFF_VOLATILE");
+    assert!(v[2].value == -1); assert!(v[2].name == "IFF_ALL");
+
+    let b = parse_buff(Cursor::new(s.as_bytes()), false, None, "<string>", &[], &mut SymbolTable::new()).unwrap();
+    let v = &b[0].variants;
+    assert!(v[0].value == 3); assert!(v[0].name == "IFF_MASK");
+    assert!(v[1].value == 128); assert!(v[1].name == "IFF_HALF");
+}
+
+#[test]
+fn test_parse_buff_symbols() {
+    use std::io::Cursor;
+    let mut symbols = SymbolTable::new();
+    let s = "RTM_BASE = 16,\n\
+             RTM_NEWLINK = RTM_BASE,\n\
+             RTM_DELLINK,\n\
+             RTM_MAX = RTM_BASE + 4,";
+    let b = parse_buff(Cursor::new(s.as_bytes()), true, None, "<string>", &[], &mut symbols).unwrap();
+    let v = &b[0].variants;
+    assert!(v[2].value == 17); assert!(v[2].name == "RTM_DELLINK");
+    assert!(v[3].value == 20); assert!(v[3].name == "RTM_MAX");
+
+    // symbols carry over from one input to the next
+    let s = "#define NETLINK_SOCK_DIAG 4\n\
+             #define NETLINK_INET_DIAG NETLINK_SOCK_DIAG\n\
+             #define NETLINK_RTM_MAX (RTM_MAX + 1)";
+    let b = parse_buff(Cursor::new(s.as_bytes()), false, None, "<string>", &[], &mut symbols).unwrap();
+    let v = &b[0].variants;
+    assert!(b.len() == 1);
+    assert!(v[1].value == 4); assert!(v[1].name == "NETLINK_INET_DIAG");
+    assert!(v[2].value == 21); assert!(v[2].name == "NETLINK_RTM_MAX");
+
+    // a #define that can't be evaluated is only an error if its block was
+    // asked for
+    let s = "#define NETLINK_BAD (NETLINK_MISSING + 1)\n\
+             #define NLMSG_HDRLEN ((int) NLMSG_ALIGN(sizeof(struct nlmsghdr)))\n\
+             #define NLMSG_DONE 0x3";
+    let b = parse_buff(Cursor::new(s.as_bytes()), false, None, "<string>", &[], &mut symbols).unwrap();
+    assert_eq!(b.len(), 1);
+    assert_eq!(b[0].variants[0].name, "NLMSG_DONE");
+    assert!(!symbols.contains_key("NLMSG_HDRLEN"));
+    let wanted = [String::from("NETLINK")];
+    let e = parse_buff(Cursor::new(s.as_bytes()), false, None, "<string>", &wanted, &mut symbols).unwrap_err();
+    assert!(e.to_string().contains("unknown identifier 'NETLINK_MISSING'"));
+}
+
+#[test]
+fn test_parse_buff_enum_block() {
+    use std::io::Cursor;
+    let s = "/* leading comment */\n\
+             enum net_device_flags {\n\
+                 IFF_UP = 1<<0, /* sysfs */\n\
+                 IFF_BROADCAST = 1<<1,\n\
+                 IFF_DEBUG = 1<<2\n\
+             };";
+    let b = parse_buff(Cursor::new(s.as_bytes()), true, None, "<string>", &[], &mut SymbolTable::new()).unwrap();
+    let v = &b[0].variants;
+    assert!(b[0].name == Some(String::from("net_device_flags")));
+    assert!(v.len() == 3);
+    assert!(v[2].value == 4); assert!(v[2].name == "IFF_DEBUG");
+
+    let s = "typedef enum state\n\
+             {\n\
+                 STATE_IDLE,\n\
+                 STATE_BUSY,\n\
+             } state_t;";
+    let b = parse_buff(Cursor::new(s.as_bytes()), true, None, "<string>", &[], &mut SymbolTable::new()).unwrap();
+    let v = &b[0].variants;
+    assert!(b[0].name == Some(String::from("state_t")));
+    assert!(v.len() == 2);
+
+    // more than one value on a line
+    let s = "enum color {\n\
+                 RED = 1, GREEN = MAX(2, 1),\n\
+                 BLUE\n\
+             };\n\
+             enum size { SMALL, LARGE = 4, HUGE } size_t;\n\
+             A = 1, B,";
+    // the comma in MAX(2, 1) doesn't split it up
+    match parse_buff(Cursor::new(s.as_bytes()), true, None, "<string>", &[], &mut SymbolTable::new()) {
+        Err(Error::Eval(ref e)) => assert_eq!(e.msg, "unexpected character ','"),
+        r => panic!("unexpected result {:?}", r),
+    }
+    let s = s.replace("MAX(2, 1)", "1 + 1");
+    let b = parse_buff(Cursor::new(s.as_bytes()), true, None, "<string>", &[], &mut SymbolTable::new()).unwrap();
+    let names: Vec<_> = b.iter().map(|b| b.variants.iter().map(|v| (&v.name[..], v.value))
+                                                    .collect::<Vec<_>>()).collect();
+    assert_eq!(names, vec![vec![("RED", 1), ("GREEN", 2), ("BLUE", 3)],
+                           vec![("SMALL", 0), ("LARGE", 4), ("HUGE", 5)],
+                           vec![("A", 1), ("B", 2)]]);
+    assert_eq!(b[0].variants[1].span, Span { line: 2, column: 10, len: 5 });
+    assert_eq!(b[1].name, Some(String::from("size_t")));
+}
+
+#[test]
+fn test_to_camel_case() {
+    assert_eq!(to_camel_case("net_device_flags"), "NetDeviceFlags");
+    assert_eq!(to_camel_case("state_t"), "State");
+    assert_eq!(to_camel_case("RTNLGRP"), "Rtnlgrp");
+    assert_eq!(to_camel_case("_fooBar_baz"), "FooBarBaz");
+}
+
+#[test]
+fn test_parse_buff_multiple() {
+    use std::io::Cursor;
+    let s = "enum {\n\
+                 RTM_BASE = 16,\n\
+                 RTM_NEWLINK = 16,\n\
+             };\n\
+             enum rtnetlink_groups {\n\
+                 RTNLGRP_NONE,\n\
+                 RTNLGRP_LINK,\n\
+             };";
+    let b = parse_buff(Cursor::new(s.as_bytes()), true, None, "<string>", &[], &mut SymbolTable::new()).unwrap();
+    assert!(b.len() == 2);
+    assert!(b[0].name == Some(String::from("RTM")));
+    assert!(b[1].name == Some(String::from("rtnetlink_groups")));
+    assert!(b[1].variants[1].value == 1); assert!(b[1].variants[1].name == "RTNLGRP_LINK");
+
+    let s = "enum {\n\
+                 IF_OPER_UNKNOWN,\n\
+                 IF_OPER_UP = 6,\n\
+             };\n\
+             enum {\n\
+                 IF_LINK_MODE_DEFAULT,\n\
+                 IF_LINK_MODE_DORMANT,\n\
+                 __IF_LINK_MODE_MAX,\n\
+             };";
+    let b = parse_buff(Cursor::new(s.as_bytes()), true, None, "<string>", &[], &mut SymbolTable::new()).unwrap();
+    assert!(b[0].name == Some(String::from("IF_OPER")));
+    assert!(b[1].name == Some(String::from("IF_LINK_MODE")));
+
+    let s = "#define NETLINK_ROUTE 0\n\
+             #define NETLINK_UNUSED 1\n\
+             \n\
+             #define NLM_F_REQUEST 1\n\
+             #define NLM_F_MULTI 2\n\
+             #define ZERO 0\n\
+             #define ONE 1";
+    let b = parse_buff(Cursor::new(s.as_bytes()), false, None, "<string>", &[], &mut SymbolTable::new()).unwrap();
+    assert!(b.len() == 3);
+    assert!(b[0].name == Some(String::from("NETLINK"))); assert!(b[0].variants.len() == 2);
+    assert!(b[1].name == Some(String::from("NLM"))); assert!(b[1].variants.len() == 2);
+    assert!(b[2].name == None); assert!(b[2].variants.len() == 2);
+
+    // a prefix that comes back later is still the same block
+    let s = format!("{}\n#define NETLINK_XFRM 6", s);
+    let b = parse_buff(Cursor::new(s.as_bytes()), false, None, "<string>", &[], &mut SymbolTable::new()).unwrap();
+    assert!(b.len() == 3);
+    assert!(b[0].name == Some(String::from("NETLINK"))); assert!(b[0].variants.len() == 3);
+    assert!(b[0].variants[2].name == "NETLINK_XFRM");
+    let mut fa = FileArgs::default();
+    fa.define = true;
+    fa.rename.insert(String::from("NETLINK"), String::from("Protocol"));
+    assert!(process_str(&s, &fa).unwrap().contains("pub enum Protocol {"));
+}
+
+#[test]
+fn test_repr_type() {
+    let v = vec![Variant::new("A", -1), Variant::new("B", 0x7FFFFFFF)];
+    assert_eq!(repr_type(&v).unwrap(), "i32");
+    let v = vec![Variant::new("A", 0), Variant::new("B", 0x80000000)];
+    assert_eq!(repr_type(&v).unwrap(), "u32");
+    let v = vec![Variant::new("A", -1), Variant::new("B", 0xFFFFFFFF)];
+    assert_eq!(repr_type(&v).unwrap(), "i64");
+    let v = vec![Variant::new("A", 0), Variant::new("B", 0xFFFFFFFFFFFFFFFF)];
+    assert_eq!(repr_type(&v).unwrap(), "u64");
+    let v = vec![Variant::new("A", -1), Variant::new("B", 0xFFFFFFFFFFFFFFFF)];
+    assert!(repr_type(&v).is_err());
+}
+
+#[test]
+fn test_write_wide_values() {
+    use std::io::Cursor;
+    let s = "FLAG_LOW = 1,\n\
+             FLAG_HIGH = 1 << 63,";
+    let b = parse_buff(Cursor::new(s.as_bytes()), true, None, "<string>", &[], &mut SymbolTable::new()).unwrap();
+
+    let mut out = Vec::new();
+    let mut fa = FileArgs::default();
+    fa.hex = Some(true);
+    FormatOutputEnum.write(&mut out, &String::from("Flags"), &fa, &b[0].variants).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("#[repr(u64)]"));
+    assert!(out.contains("FLAG_HIGH = 0x8000000000000000,"));
+
+    let mut out = Vec::new();
+    FormatOutputFromPrimative.write(&mut out, &String::from("Flags"), &FileArgs::default(),
+                                    &b[0].variants).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    // too big for from_i64(), but not for from_u64()
+    assert!(out.matches("9223372036854775808 => Some(Flags::FLAG_HIGH)").count() == 1);
+}
+
+#[test]
+fn test_write_negative_values() {
+    use std::io::Cursor;
+    let s = "#define ERR_PERM (-1)\n\
+             #define ERR_TIMEOUT (-110)\n\
+             #define ERR_NONE 0x0U";
+    let b = parse_buff(Cursor::new(s.as_bytes()), false, None, "<string>", &[], &mut SymbolTable::new()).unwrap();
+
+    let mut out = Vec::new();
+    let mut fa = FileArgs::default();
+    fa.hex = Some(true);
+    FormatOutputEnum.write(&mut out, &String::from("Err"), &fa, &b[0].variants).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("#[repr(i32)]"));
+    assert!(out.contains("ERR_TIMEOUT = 0xFFFFFF92u32 as i32,"));
+    assert!(out.contains("ERR_PERM = 0xFFFFFFFFu32 as i32,"));
+    assert!(out.contains("ERR_NONE = 0x0,"));
+
+    let mut out = Vec::new();
+    FormatOutputFromPrimative.write(&mut out, &String::from("Err"), &fa, &b[0].variants).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    // negative values can't come from a u64
+    assert!(out.matches("-0x6E => Some(Err::ERR_TIMEOUT)").count() == 1);
+    assert!(out.matches("0x0 => Some(Err::ERR_NONE)").count() == 2);
+}
+
+#[test]
+fn test_parse_buff_errors() {
+    use std::io::Cursor;
+    let s = "enum rt {\n\
+             \tRTM_BASE = 16, /* base */\n\
+             \tRTM_MAX = RTM_BASE + RTM_MISSING,\n\
+             };";
+    let path = PathBuf::from("rt.in");
+    let e = parse_buff(Cursor::new(s.as_bytes()), true, Some(&path), "rt.in", &[], &mut SymbolTable::new()).unwrap_err();
+    let e = match e {
+        Error::Eval(e) => e,
+        e => panic!("unexpected error {:?}", e),
+    };
+    assert_eq!(e.path, Some(path.clone()));
+    assert_eq!(e.line, 3);
+    assert_eq!(e.column, 23);
+    assert_eq!(e.snippet(), "RTM_MISSING");
+    assert_eq!(e.text, "\tRTM_MAX = RTM_BASE + RTM_MISSING,");
+    assert_eq!(e.to_string(), "rt.in:3:23: unknown identifier 'RTM_MISSING'");
+
+    // comments before the value don't throw the column off
+    let s = "#define /* a */ BAD_SHIFT /* b */ (1 << 99)";
+    let wanted = [String::from("BAD")];
+    let e = parse_buff(Cursor::new(s.as_bytes()), false, None, "<string>", &wanted, &mut SymbolTable::new()).unwrap_err();
+    let e = match e {
+        Error::Eval(e) => e,
+        e => panic!("unexpected error {:?}", e),
+    };
+    assert_eq!(e.line, 1);
+    assert_eq!(e.snippet(), "<<");
+
+    // a line in an enum block that isn't a value isn't skipped over
+    let s = "enum e {\n\tA = 1,\n\tD 4,\n};";
+    match parse_buff(Cursor::new(s.as_bytes()), true, None, "<string>", &[], &mut SymbolTable::new()) {
+        Err(Error::Parse(ref e)) => {
+            assert_eq!((e.line, e.column), (3, 2));
+            assert_eq!(e.snippet(), "D 4,");
+        }
+        r => panic!("unexpected result {:?}", r),
+    }
+    // but the rest of a header is
+    let s = "#if __UAPI_DEF_IF_NET_DEVICE_FLAGS_LOWER_UP_DORMANT_ECHO != 0 || \\\n\
+             \x20   __UAPI_DEF_IF_NET_DEVICE_FLAGS != 0\n\
+             struct rtattr {\n\
+             \tunsigned short rta_len;\n\
+             };\n\
+             enum color { RED, GREEN, BLUE };\n\
+             typedef enum {\n\
+             \tSTATE_IDLE,\n\
+             } state_t;\n\
+             state_t current;\n\
+             #endif";
+    let blocks = parse_buff(Cursor::new(s.as_bytes()), true, None, "<string>", &[], &mut SymbolTable::new()).unwrap();
+    assert_eq!(blocks.len(), 2);
+    assert_eq!(blocks[0].name, Some(String::from("color")));
+    assert_eq!(blocks[0].variants.len(), 3);
+    assert_eq!(blocks[1].name, Some(String::from("state_t")));
+    assert_eq!(blocks[1].variants[0].name, "STATE_IDLE");
+
+    // non-ASCII characters are reported rather than panicking
+    for s in &["A = 1é,", "A = é,", "A = 2 × 3,"] {
+        match parse_buff(Cursor::new(s.as_bytes()), true, None, "<string>", &[], &mut SymbolTable::new()) {
+            Err(Error::Eval(ref e)) => assert!(e.msg.starts_with("unexpected character")),
+            r => panic!("unexpected result {:?} for {}", r, s),
+        }
+    }
+}
+
+#[test]
+fn test_parse_toml_errors() {
+    let table = toml::Parser::new("[other]\nname = \"A\"").parse().unwrap();
+    match parse_toml_table(&table) {
+        Err(Error::Config { path: None, ref msg, .. }) =>
+            assert_eq!(msg, "couldn't find a rust-enum-derive table"),
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    let table = toml::Parser::new("[rust-enum-derive]\nhex = \"yes\"").parse().unwrap();
+    let e = parse_toml_table(&table).unwrap_err().with_path(&PathBuf::from("a.toml"));
+    assert_eq!(e.to_string(), "a.toml: hex wasn't available as bool");
+
+    let e = Error::from(::std::io::Error::new(::std::io::ErrorKind::NotFound, "gone"));
+    assert!(::std::error::Error::source(&e).is_some());
+
+    // the error from opening a file is kept, with the path
+    let path = PathBuf::from("/nonexistent/a.in");
+    let e = process(Some(&path), None, &FileArgs::default()).unwrap_err();
+    match e {
+        Error::Io { path: Some(ref p), ref source } => {
+            assert_eq!(p, &path);
+            assert_eq!(source.kind(), ::std::io::ErrorKind::NotFound);
+        }
+        ref e => panic!("unexpected error {:?}", e),
+    }
+    assert!(e.to_string().starts_with("/nonexistent/a.in: "));
+    let source = ::std::error::Error::source(&e).unwrap();
+    assert!(source.downcast_ref::<::std::io::Error>().is_some());
+}
+
+#[test]
+fn test_parse_and_generate() {
+    let s = "/* leading comment */\n\
+             enum net_device_flags {\n\
+                 /* sysfs */\n\
+                 IFF_UP = 1<<0,\n\
+                 IFF_BROADCAST = 1<<1, // broadcast\n\
+             };";
+    let def = parse(s, &ParseOptions::default()).unwrap();
+    assert_eq!(def.name, Some(String::from("net_device_flags")));
+    assert_eq!(def.comment, Some(String::from("leading comment")));
+    assert_eq!(def.span, Span { line: 2, column: 1, len: 23 });
+    assert_eq!(def.variants[0].comment, Some(String::from("sysfs")));
+    assert_eq!(def.variants[0].span, Span { line: 4, column: 1, len: 6 });
+    assert_eq!(def.variants[1].comment, Some(String::from("broadcast")));
+    assert_eq!(def.repr().unwrap(), "i32");
+
+    let mut options = ParseOptions::default();
+    options.enum_name = Some(String::from("missing"));
+    assert!(parse(s, &options).is_err());
+
+    // the definition can be changed before generating code from it
+    let mut def = def;
+    def.variants.push(Variant::new("IFF_DEBUG", 1 << 2));
+    let mut fa = FileArgs::default();
+    fa.display = true;
+    let out = generate(&def, &fa).unwrap();
+    assert!(out.contains("pub enum NetDeviceFlags {"));
+    assert!(out.contains("    IFF_DEBUG = 4,\n"));
+    assert!(out.contains("NetDeviceFlags::IFF_DEBUG => write!(f, \"IFF_DEBUG\"),"));
+
+    assert!(generate(&EnumDef::new(None), &fa).is_err());
+}
+
+#[test]
+fn test_process_str() {
+    let s = "#define NETLINK_ROUTE 0\n\
+             #define NETLINK_UNUSED 1\n\
+             #define NLM_F_REQUEST 1";
+    let mut fa = FileArgs::default();
+    fa.define = true;
+    fa.fromstr = true;
+    let out = process_str(s, &fa).unwrap();
+    assert!(out.contains("pub enum Netlink {"));
+    assert!(out.contains("pub enum Nlm {"));
+    assert!(out.contains("\"NETLINK_UNUSED\" => Ok(Netlink::NETLINK_UNUSED),"));
+
+    let mut out = Vec::new();
+    process_io(s.as_bytes(), &mut out, &fa).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), process_str(s, &fa).unwrap());
+
+    fa.define = false;
+    match process_str(s, &fa) {
+        Err(Error::NoInput(ref input)) => assert_eq!(input, "the string"),
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    // parse errors say where the input came from
+    let s = "A = 1,\nB = MISSING,";
+    assert_eq!(process_str(s, &fa).unwrap_err().to_string(),
+               "<string>:2:5: unknown identifier 'MISSING'");
+    let e = process_io(s.as_bytes(), Vec::new(), &fa).unwrap_err();
+    assert_eq!(e.to_string(), "<input>:2:5: unknown identifier 'MISSING'");
+    let mut options = ParseOptions::default();
+    assert!(parse(s, &options).unwrap_err().to_string().starts_with("<string>:2:5:"));
+    options.path = Some(PathBuf::from("a.in"));
+    assert!(parse(s, &options).unwrap_err().to_string().starts_with("a.in:2:5:"));
+}
+
+#[test]
+fn test_write_tryfrom() {
+    let v = vec![Variant::new("A", 0), Variant::new("B", 200)];
+    assert_eq!(holding_types(&v), vec!["u8", "u16", "i16", "u32", "i32", "u64", "i64"]);
+    let v = vec![Variant::new("A", -1), Variant::new("B", 0xFFFFFFFF)];
+    assert_eq!(holding_types(&v), vec!["i64"]);
+
+    let mut fa = FileArgs::default();
+    fa.hex = Some(true);
+    let mut out = Vec::new();
+    FormatOutputTryFrom.write(&mut out, &String::from("Err"), &fa, &v).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("pub struct TryFromErrError<T>(pub T);"));
+    assert!(out.contains("impl ::std::convert::TryFrom<i64> for Err {"));
+    assert!(out.contains("            -0x1 => Ok(Err::A),\n"));
+    assert!(out.contains("            _ => Err(TryFromErrError(n))\n"));
+    assert_eq!(out.matches("TryFrom<").count(), 1);
+}
+
+#[test]
+fn test_write_raw() {
+    let v = vec![Variant::new("ERR_PERM", -1), Variant::new("ERR_NONE", 0)];
+    let mut fa = FileArgs::default();
+    fa.hex = Some(true);
+    let mut out = Vec::new();
+    FormatOutputRaw.write(&mut out, &String::from("Err"), &fa, &v).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("    pub const fn as_raw(&self) -> i32 {\n"));
+    assert!(out.contains("            Err::ERR_PERM => 0xFFFFFFFFu32 as i32,\n"));
+    assert!(out.contains("impl From<Err> for i32 {\n"));
+}
+
+#[test]
+fn test_choose_repr() {
+    let v = vec![Variant::new("A", 0), Variant::new("B", 200)];
+    let r = |s: &str| choose_repr(&v, Some(&String::from(s)));
+    assert_eq!(choose_repr(&v, None).unwrap(), "i32");
+    assert_eq!(r("auto").unwrap(), "u8");
+    assert_eq!(r("C").unwrap(), "i32");
+    assert_eq!(r("u16").unwrap(), "u16");
+    match r("i8") {
+        Err(Error::Generate(ref msg)) => assert_eq!(msg, "values from 0 to 200 don't fit in i8"),
+        r => panic!("unexpected result {:?}", r),
+    }
+    match r("int") {
+        Err(Error::Config { .. }) => (),
+        r => panic!("unexpected result {:?}", r),
+    }
+    let v = vec![Variant::new("A", -1), Variant::new("B", 200)];
+    assert_eq!(choose_repr(&v, Some(&String::from("auto"))).unwrap(), "i16");
+
+    let mut fa = FileArgs::default();
+    fa.hex = Some(true);
+    fa.repr = Some(String::from("i8"));
+    let mut out = Vec::new();
+    FormatOutputEnum.write(&mut out, &String::from("Err"), &fa, &vec![Variant::new("A", -1)]).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("#[repr(i8)]"));
+    assert!(out.contains("A = 0xFFu8 as i8,"));
+
+    fa.repr = Some(String::from("C"));
+    let mut out = Vec::new();
+    FormatOutputEnum.write(&mut out, &String::from("Err"), &fa, &vec![Variant::new("A", -1)]).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("#[repr(C)]"));
+    assert!(out.contains("A = -0x1,"));
+}
+
+#[test]
+fn test_write_pretty_fmt() {
+    let s = "M_NONE = 0,\n\
+             M_READ = 1,\n\
+             M_WRITE = 2,\n\
+             M_RW = 3,\n\
+             M_BIG = 0x100000000,";
+    let mut fa = FileArgs::default();
+    fa.pretty_fmt = Some(true);
+    let out = process_str(s, &fa).unwrap();
+    assert!(out.contains("pub struct NamePrettyFmt(pub u64);"));
+    // masks come first so that they're matched before their parts
+    let rw = out.find("(\"M_RW\", 3),").unwrap();
+    assert!(rw < out.find("(\"M_READ\", 1),").unwrap());
+    assert!(rw < out.find("(\"M_WRITE\", 2),").unwrap());
+    assert!(out.contains("(\"M_BIG\", 4294967296),"));
+    assert!(out.contains("            return f.write_str(\"M_NONE\")\n"));
+    assert!(out.contains("    pub fn pretty_fmt(flags: u64) -> NamePrettyFmt {"));
+    // it doesn't need any of the other traits
+    assert!(!out.contains("FromPrimitive"));
+}
+
+#[test]
+fn test_auto() {
+    let s = "enum seq {\n\
+             SEQ_A,\n\
+             SEQ_B,\n\
+             };\n\
+             enum bits {\n\
+             BITS_A = 1<<0,\n\
+             BITS_B = 1<<1,\n\
+             BITS_AB = BITS_A | BITS_B,\n\
+             };\n\
+             enum shifts {\n\
+             SHIFTS_A = 1,\n\
+             SHIFTS_AB = (1<<0)|(1<<1),\n\
+             };\n\
+             enum odd {\n\
+             ODD_A = 1,\n\
+             ODD_B = 3,\n\
+             };\n";
+    let flags: Vec<bool> = parse_all(s, &ParseOptions::default()).unwrap()
+                               .iter().map(|b| b.is_flags()).collect();
+    // BITS_AB is made of the flags before it, ODD_B isn't
+    assert_eq!(flags, vec![false, true, true, false]);
+    let def = parse("A = 1,\nB = 2,\nC = 3,", &ParseOptions::default()).unwrap();
+    assert!(!def.is_flags());
+    let def = parse("A = 1,\nB = 2,\nAB = A | B,", &ParseOptions::default()).unwrap();
+    assert!(def.is_flags());
+    // fields rather than flags
+    let def = parse("A = 1 << 4,\nB = 2 << 4,\nC = 3 << 4,", &ParseOptions::default()).unwrap();
+    assert!(!def.is_flags());
+    let def = parse("X = 1,\nY = 2,\nA = (Y << 8) | (X << 8),", &ParseOptions::default()).unwrap();
+    assert!(!def.is_flags());
+    let def = parse("A = 1U << 0,\nB = (1U << 0) | (1U << 1),", &ParseOptions::default()).unwrap();
+    assert!(def.is_flags());
+
+    let mut fa = FileArgs::default();
+    fa.auto = true;
+    fa.enums = Some(vec![String::from("seq"), String::from("shifts")]);
+    let out = process_str(s, &fa).unwrap();
+    assert!(out.contains("    SEQ_B = 1,"));
+    assert!(out.contains("    SHIFTS_AB = 0x3,"));
+    assert!(out.contains("pub struct ShiftsPrettyFmt(pub u32);"));
+    assert!(!out.contains("SeqPrettyFmt"));
+
+    // hex or pretty_fmt turned off by hand beats auto
+    let table = toml::Parser::new("[rust-enum-derive]\n\
+                                   auto = true\n\
+                                   hex = false\n\
+                                   enums = [\"shifts\"]").parse().unwrap();
+    let mut fa = parse_toml_table(&table).unwrap();
+    assert_eq!(fa.hex, Some(false));
+    assert_eq!(fa.pretty_fmt, None);
+    let out = process_str(s, &fa).unwrap();
+    assert!(out.contains("    SHIFTS_AB = 3,"));
+    assert!(out.contains("pub struct ShiftsPrettyFmt(pub u32);"));
+    fa.pretty_fmt = Some(false);
+    assert!(!process_str(s, &fa).unwrap().contains("ShiftsPrettyFmt"));
+}
+
+#[test]
+fn test_write_fromstr() {
+    let s = "E_ONE = 1,\n\
+             E_TWO = 2,";
+    let mut fa = FileArgs::default();
+    fa.fromstr = true;
+    let out = process_str(s, &fa).unwrap();
+    assert!(out.contains("pub struct ParseNameError(pub String);"));
+    assert!(out.contains("write!(f, \"{:?} isn't a valid Name, expected one of E_ONE, E_TWO\", self.0)"));
+    assert!(out.contains("impl ::std::error::Error for ParseNameError {}"));
+    assert!(out.contains("    type Err = ParseNameError;"));
+    assert!(out.contains("            _ => Err(ParseNameError(String::from(s)))"));
+}
+
+#[test]
+fn test_duplicates() {
+    let s = "RTM_BASE = 16,\n\
+             RTM_NEWLINK = 16,\n\
+             RTM_DELLINK,";
+    let mut fa = FileArgs::default();
+    fa.fromstr = true;
+    fa.fromprimative = true;
+    let out = process_str(s, &fa).unwrap();
+    assert_eq!(out.matches(" = 16,").count(), 1);
+    assert!(out.contains("    pub const RTM_NEWLINK: Name = Name::RTM_BASE;"));
+    assert!(out.contains("            \"RTM_NEWLINK\" => Ok(Name::RTM_BASE),"));
+    assert_eq!(out.matches("            16 => Some(Name::RTM_BASE),").count(), 2);
+
+    fa.duplicates = Some(String::from("drop"));
+    let out = process_str(s, &fa).unwrap();
+    assert!(!out.contains("RTM_NEWLINK"));
+
+    fa.duplicates = Some(String::from("error"));
+    match process_str(s, &fa) {
+        Err(Error::Generate(ref msg)) => {
+            assert_eq!(msg, "RTM_NEWLINK has the same value as RTM_BASE (16)")
+        }
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    // two names for one value are still different variants
+    assert!(Variant::new("RTM_BASE", 16) != Variant::new("RTM_NEWLINK", 16));
+    assert!(Variant::new("RTM_BASE", 16) == Variant::new("RTM_BASE", 16));
+}
+
+#[test]
+fn test_order() {
+    let s = "B_TWO = 2,\n\
+             C_ONE = 1,\n\
+             A_THREE = 3,\n\
+             D_ALIAS = 1,";
+    let mut fa = FileArgs::default();
+    fa.default = true;
+    fa.fromstr = true;
+    let order = |fa: &FileArgs| {
+        let out = process_str(s, fa).unwrap();
+        let pos: Vec<usize> = ["    A_THREE = 3,", "    B_TWO = 2,", "    C_ONE = 1,"].iter()
+                                  .map(|n| out.find(n).unwrap()).collect();
+        let default = String::from(out.split("        Name::").nth(1).unwrap()
+                                      .split('\n').next().unwrap());
+        (pos[0] < pos[1], pos[1] < pos[2], default)
+    };
+    assert_eq!(order(&fa), (false, false, String::from("C_ONE")));
+    fa.order = Some(String::from("source"));
+    assert_eq!(order(&fa), (false, true, String::from("B_TWO")));
+    fa.order = Some(String::from("name"));
+    assert_eq!(order(&fa), (true, true, String::from("A_THREE")));
+    // the alias still parses as the variant that it's an alias of
+    assert!(process_str(s, &fa).unwrap().contains("\"D_ALIAS\" => Ok(Name::C_ONE),"));
+
+    fa.order = Some(String::from("random"));
+    assert!(process_str(s, &fa).is_err());
+}
+
+#[test]
+fn test_default() {
+    let s = "STATE_INIT = 0,\n\
+             STATE_IDLE = 1,\n\
+             STATE_WAITING = 1,\n\
+             STATE_DONE = 2,";
+    let table = toml::Parser::new("[rust-enum-derive]\n\
+                                   default = \"STATE_WAITING\"").parse().unwrap();
+    let mut fa = parse_toml_table(&table).unwrap();
+    assert!(fa.default);
+    let out = process_str(s, &fa).unwrap();
+    // an alias gives the variant it's an alias of
+    assert!(out.contains("    fn default() -> Name {\n        Name::STATE_IDLE\n"));
+
+    let table = toml::Parser::new("[rust-enum-derive]\n\
+                                   default = 2\n\
+                                   default_attr = true\n\
+                                   derive = \"Debug\"").parse().unwrap();
+    fa = parse_toml_table(&table).unwrap();
+    let out = process_str(s, &fa).unwrap();
+    assert!(out.contains("#[derive(Debug, Default)]\n"));
+    assert!(out.contains("    #[default]\n    STATE_DONE = 2,\n"));
+    assert!(!out.contains("impl Default"));
+
+    fa.default_value = Some(String::from("STATE_GONE"));
+    match process_str(s, &fa) {
+        Err(Error::Config { ref msg, .. }) => {
+            assert_eq!(msg, "can't use STATE_GONE as the default for Name: \
+                             unknown identifier 'STATE_GONE'")
+        }
+        r => panic!("unexpected result {:?}", r),
+    }
+    fa.default_value = Some(String::from("3"));
+    assert!(process_str(s, &fa).is_err());
+}
+
+#[test]
+fn test_naming() {
+    assert_eq!(convert_case("QINQ_TAG", "UpperCamelCase").unwrap(), "QinqTag");
+    assert_eq!(convert_case("QINQ_TAG", "lowerCamelCase").unwrap(), "qinqTag");
+    assert_eq!(convert_case("QinqTag", "lowerCamelCase").unwrap(), "qinqTag");
+    assert_eq!(convert_case("QINQ__TAG", "snake_case").unwrap(), "qinq_tag");
+    assert_eq!(convert_case("qinq_tag", "SCREAMING_SNAKE_CASE").unwrap(), "QINQ_TAG");
+    assert!(convert_case("QINQ_TAG", "kebab-case").is_err());
+
+    let s = "VLAN_8021Q = 1,\n\
+             VLAN_QINQ_TAG = 2,\n\
+             VLAN = 3,";
+    let mut fa = FileArgs::default();
+    fa.strip_prefix = Some(String::from("VLAN_"));
+    fa.case = Some(String::from("UpperCamelCase"));
+    fa.display = true;
+    fa.fromstr = true;
+    let out = process_str(s, &fa).unwrap();
+    assert!(out.starts_with("#[allow(dead_code)]\n"));
+    assert!(out.contains("    _8021q = 1,\n    QinqTag = 2,\n    Vlan = 3,\n"));
+    assert!(out.contains("            Name::QinqTag => write!(f, \"VLAN_QINQ_TAG\"),"));
+    assert!(out.contains("            \"VLAN_QINQ_TAG\" => Ok(Name::QinqTag),"));
+
+    fa.display_names = Some(String::from("rust"));
+    let out = process_str(s, &fa).unwrap();
+    assert!(out.contains("            Name::QinqTag => write!(f, \"QinqTag\"),"));
+    assert!(out.contains("            \"QinqTag\" => Ok(Name::QinqTag),"));
+
+    // a name that's already set is kept
+    let mut def = parse(s, &ParseOptions::default()).unwrap();
+    def.variants[2].ident = Some(String::from("Untagged"));
+    assert!(generate(&def, &fa).unwrap().contains("    Untagged = 3,\n"));
+
+    // the default can be picked by its rust name
+    fa.default = true;
+    fa.default_value = Some(String::from("QinqTag"));
+    assert!(process_str(s, &fa).unwrap().contains("        Name::QinqTag\n"));
+    assert!(generate(&def, &fa).unwrap().contains("        Name::QinqTag\n"));
+    fa.default = false;
+    fa.default_value = None;
+
+    let s = "VLAN_QINQ_TAG = 2,\n\
+             VLAN_QINQ__TAG = 3,";
+    match process_str(s, &fa) {
+        Err(Error::Generate(ref msg)) => {
+            assert_eq!(msg, "VLAN_QINQ_TAG and VLAN_QINQ__TAG would both be named QinqTag")
+        }
+        r => panic!("unexpected result {:?}", r),
+    }
+}
+
+#[test]
+fn test_strip_prefix() {
+    let s = "RTM_NEW_LINK = 16,\n\
+             RTM_NEW_ADDR = 20,\n\
+             RTM_NEW_TYPE = 24,";
+    let def = parse(s, &ParseOptions::default()).unwrap();
+    assert_eq!(def.common_prefix(), Some(String::from("RTM_NEW_")));
+    let def = parse("RTM_NEW = 1,\nRTM_NEW_LINK = 2,", &ParseOptions::default()).unwrap();
+    assert_eq!(def.common_prefix(), Some(String::from("RTM_")));
+    let def = parse("LINK = 1,\nADDR = 2,", &ParseOptions::default()).unwrap();
+    assert_eq!(def.common_prefix(), None);
+
+    let table = toml::Parser::new("[rust-enum-derive]\n\
+                                   strip_prefix = \"auto\"\n\
+                                   case = \"snake_case\"").parse().unwrap();
+    let mut fa = parse_toml_table(&table).unwrap();
+    fa.display = true;
+    fa.display_names = Some(String::from("rust"));
+    let out = process_str(s, &fa).unwrap();
+    // keywords are escaped, but not in the names that Display writes
+    assert!(out.contains("    link = 16,\n    addr = 20,\n    r#type = 24,\n"));
+    assert!(out.contains("            Name::r#type => write!(f, \"type\"),"));
+
+    fa.strip_prefix = Some(String::from("RTM_NEW_LINK"));
+    match process_str(s, &fa) {
+        Err(Error::Generate(ref msg)) => {
+            assert_eq!(msg, "RTM_NEW_LINK has no name left once RTM_NEW_LINK is stripped")
+        }
+        r => panic!("unexpected result {:?}", r),
+    }
+    fa.strip_prefix = Some(String::from("auto"));
+    fa.case = Some(String::from("lowerCamelCase"));
+    assert!(process_str("E_SELF = 1,\nE_X = 2,", &fa).is_err());
+    fa.strip_prefix = Some(String::from("FOO_"));
+    fa.case = None;
+    match process_str("FOO__ = 1,\nFOO_X = 2,", &fa) {
+        Err(Error::Generate(ref msg)) => {
+            assert_eq!(msg, "FOO__ would be named _, which is a keyword")
+        }
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    let table = toml::Parser::new("[rust-enum-derive]\n\
+                                   strip_prefix = false").parse().unwrap();
+    assert_eq!(parse_toml_table(&table).unwrap().strip_prefix, None);
+    let table = toml::Parser::new("[rust-enum-derive]\n\
+                                   strip_prefix = 1").parse().unwrap();
+    assert!(parse_toml_table(&table).is_err());
+}
+
+#[test]
+fn test_write_serde() {
+    let s = "E_ONE = 1,\n\
+             E_TWO = 2,";
+    let table = toml::Parser::new("[rust-enum-derive]\n\
+                                   serialize = true\n\
+                                   deserialize = true\n\
+                                   [rust-enum-derive.serde_rename]\n\
+                                   E_TWO = \"two\"").parse().unwrap();
+    let mut fa = parse_toml_table(&table).unwrap();
+    let out = process_str(s, &fa).unwrap();
+    assert!(out.contains("            Name::E_ONE => \"E_ONE\",\n"));
+    assert!(out.contains("            Name::E_TWO => \"two\",\n"));
+    assert!(out.contains("                    \"two\" => Ok(Name::E_TWO),\n"));
+    assert!(out.contains("f.write_str(\"one of `E_ONE`, `two`\")"));
+
+    fa.serde_repr = Some(String::from("int"));
+    fa.repr = Some(String::from("u8"));
+    let out = process_str(s, &fa).unwrap();
+    assert!(out.contains("        serializer.serialize_u8(match *self {\n"));
+    assert!(out.contains("                    2 => Ok(Name::E_TWO),\n"));
+    assert!(out.contains("        deserializer.deserialize_u8(Visitor)\n"));
+
+    fa.serde_repr = Some(String::from("string"));
+    assert!(process_str(s, &fa).is_err());
+    fa.serde_repr = None;
+    fa.serde_rename.insert(String::from("E_ONE"), String::from("two"));
+    assert!(process_str(s, &fa).is_err());
+}
+
+#[test]
+fn test_write_flags() {
+    let s = "IFF_UP = 1<<0,\n\
+             IFF_DEBUG = 1<<2,\n\
+             IFF_ALL = ~0,";
+    let mut fa = FileArgs::default();
+    fa.flags = true;
+    fa.hex = Some(true);
+    fa.display = true;
+    fa.repr = Some(String::from("auto"));
+    let out = process_str(s, &fa).unwrap();
+    assert!(out.contains("pub struct Name(u8);"));
+    assert!(out.contains("    pub const IFF_ALL: Name = Name(0xFF);"));
+    assert!(out.contains("    pub const IFF_DEBUG: Name = Name(0x4);"));
+    assert!(out.contains("            (\"IFF_UP\", 0x1),"));
+    assert!(out.contains("impl ::std::ops::BitOr for Name {"));
+    assert!(out.contains("impl ::std::ops::Not for Name {"));
+    // it's not an enum, so the enum's traits aren't written
+    assert!(!out.contains("pub enum"));
+    assert_eq!(out.matches("impl ::std::fmt::Display").count(), 1);
+
+    // a mask is listed, and so named, before the flags it's made of
+    let s = "IFF_UP = 1<<0,\n\
+             IFF_BROADCAST = 1<<1,\n\
+             IFF_VOLATILE = IFF_UP | IFF_BROADCAST,";
+    let out = process_str(s, &fa).unwrap();
+    let pos: Vec<usize> = ["(\"IFF_VOLATILE\", 0x3),", "(\"IFF_UP\", 0x1),", "(\"IFF_BROADCAST\", 0x2),"]
+                              .iter().map(|n| out.find(n).unwrap()).collect();
+    assert!(pos[0] < pos[1] && pos[1] < pos[2]);
+    assert!(out.contains("            if bits != 0 && remaining & bits == bits {"));
+    assert!(out.contains("                None => f.write_str(\"(empty)\"),"));
 }
@@ -31,19 +31,50 @@ fn parse_options() -> (Args, FileArgs) {
     opts.optopt("", "input_dir", "input directory to traverse", "NAME");
     opts.optopt("o", "output", "output directory to traverse", "NAME");
     opts.optopt("", "output_dir", "output file name (stdout if not specified)", "NAME");
-    opts.optopt("", "name", "the enum name (Name if not specified)", "NAME");
+    opts.optopt("", "name", "the enum name (the C enum name in CamelCase, or Name \
+                if not specified)", "NAME");
     opts.optopt("", "derive", "Which traits to derive. Ex: \"Debug, PartialEq\"", "DERIVE");
+    opts.optopt("", "repr", "the #[repr] of the enum: u8, u16, u32, u64, i8, i16, \
+                i32, i64, C or auto (picked from the values like a C compiler \
+                would if not specified)", "TYPE");
+    opts.optopt("", "serde_repr", "how serde represents a value: name or int (name \
+                if not specified)", "REPR");
+    opts.optopt("", "duplicates", "what to do with a value that has more than one \
+                name: error, alias (the later names become associated consts) or \
+                drop (alias if not specified)", "POLICY");
+    opts.optopt("", "order", "the order of the values: source (the order of the \
+                input), value or name (value if not specified)", "ORDER");
+    opts.optopt("", "strip_prefix", "the prefix to strip from the C names to make \
+                the rust names, or auto for the prefix that they all share", "PREFIX");
+    opts.optopt("", "case", "the case of the rust names: UpperCamelCase, \
+                lowerCamelCase, snake_case or SCREAMING_SNAKE_CASE (the case of the C \
+                names if not specified)", "CASE");
+    opts.optopt("", "display_names", "the names that Display, FromStr, pretty_fmt() \
+                and serde use: c or rust (c if not specified)", "NAMES");
     opts.optflag("h", "help", "print this help menu");
     opts.optflag("", "define", "parse C #define input instead of enum");
     opts.optflag("a", "all", "implement all of the traits (equivalent to \
-                 --display --fromprimative --fromstr)");
-    opts.optflag("", "default", "implement the Default trait with the first \
-                 value");
+                 --default --display --fromprimative --fromstr --tryfrom --raw \
+                 --pretty_fmt)");
+    opts.optflagopt("", "default", "implement the Default trait with the first \
+                    value, or with the one named or valued VALUE (--default=VALUE)",
+                    "VALUE");
+    opts.optflag("", "default_attr", "derive Default and mark the default value \
+                 #[default] instead of implementing Default (needs Rust 1.62)");
     opts.optflag("", "display", "implement the std::fmt::Display trait");
     opts.optflag("", "fromprimative", "implement the num::traits::FromPrimitive trait");
     opts.optflag("", "fromstr", "implement the std::str::FromStr trait");
+    opts.optflag("", "tryfrom", "implement std::convert::TryFrom for every integer \
+                 type that can hold all of the values");
+    opts.optflag("", "raw", "implement From<Name> for the repr type, and as_raw()");
     opts.optflag("", "hex", "hexadecimal output");
     opts.optflag("", "pretty_fmt", "implement pretty_fmt()");
+    opts.optflag("", "serialize", "implement serde::Serialize");
+    opts.optflag("", "deserialize", "implement serde::Deserialize");
+    opts.optflag("", "auto", "also use --hex and --pretty_fmt for enums that look \
+                 like a set of flags");
+    opts.optflag("", "flags", "generate a bitflags style struct instead of an enum \
+                 (the other traits aren't implemented for it)");
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => { m }
         Err(f) => { panic!(f.to_string()) }
@@ -58,23 +89,36 @@ fn parse_options() -> (Args, FileArgs) {
     a.output_dir = matches.opt_str("output_dir");
     fa.name = matches.opt_str("name");
     fa.derive = matches.opt_str("derive");
+    fa.repr = matches.opt_str("repr");
     fa.define = matches.opt_present("define");
     fa.default = matches.opt_present("default");
+    fa.default_value = matches.opt_str("default");
+    fa.default_attr = matches.opt_present("default_attr");
     fa.display = matches.opt_present("display");
     fa.fromprimative = matches.opt_present("fromprimative");
-    fa.pretty_fmt = matches.opt_present("pretty_fmt");
-    if fa.pretty_fmt {
-        fa.fromprimative = true;
-        fa.display = true;
-    }
+    fa.tryfrom = matches.opt_present("tryfrom");
+    fa.raw = matches.opt_present("raw");
+    if matches.opt_present("pretty_fmt") { fa.pretty_fmt = Some(true); }
     fa.fromstr = matches.opt_present("fromstr");
-    fa.hex = matches.opt_present("hex");
+    if matches.opt_present("hex") { fa.hex = Some(true); }
+    fa.flags = matches.opt_present("flags");
+    fa.auto = matches.opt_present("auto");
+    fa.serialize = matches.opt_present("serialize");
+    fa.deserialize = matches.opt_present("deserialize");
+    fa.serde_repr = matches.opt_str("serde_repr");
+    fa.duplicates = matches.opt_str("duplicates");
+    fa.order = matches.opt_str("order");
+    fa.strip_prefix = matches.opt_str("strip_prefix");
+    fa.case = matches.opt_str("case");
+    fa.display_names = matches.opt_str("display_names");
     if matches.opt_present("all") {
         fa.default = true;
         fa.display = true;
         fa.fromprimative = true;
         fa.fromstr = true;
-        fa.pretty_fmt = true;
+        fa.tryfrom = true;
+        fa.raw = true;
+        fa.pretty_fmt = Some(true);
     }
 
     if a.input.is_some() && a.input_dir.is_some() {
@@ -91,6 +135,19 @@ fn parse_options() -> (Args, FileArgs) {
         error!("if you use --input_dir you must use --output_dir!");
         std::process::exit(1);
     }
+    if let Some(arg) = matches.free.first() {
+        // --default takes its value with an =, so --default VALUE leaves
+        // VALUE here instead
+        if args.windows(2).any(|w| w[0] == "--default" && w[1] == *arg) {
+            error!("unexpected argument {}, a value for --default has to be given as \
+                   --default={}", arg, arg);
+        }
+        else {
+            error!("unexpected argument {}", arg);
+            print_usage(&program, opts);
+        }
+        std::process::exit(1);
+    }
 
     (a, fa)
 }
@@ -103,6 +160,36 @@ fn print_usage(program: &str, opts: Options) {
     print!("{}", opts.usage(&brief));
 }
 
+// Print a ParseError the way rustc prints its errors, pointing at the
+// offending part of the input.
+fn print_diagnostic(e: &ParseError) {
+    use std::iter::repeat;
+    let line_no = e.line.to_string();
+    let gutter: String = repeat(' ').take(line_no.len()).collect();
+    // tabs are shown as four spaces, like rustc does
+    let text = e.text.replace('\t', "    ");
+    let prefix = &e.text[..std::cmp::min(e.column - 1, e.text.len())];
+    let indent: usize = prefix.chars().map(|c| if c == '\t' { 4 } else { 1 }).sum();
+    let width = std::cmp::max(1, e.snippet().chars().count());
+
+    eprintln!("error: {}", e.msg);
+    eprintln!("{}--> {}:{}:{}", gutter, e.input, e.line, e.column);
+    eprintln!("{} |", gutter);
+    eprintln!("{} | {}", line_no, text);
+    eprintln!("{} | {}{}", gutter, repeat(' ').take(indent).collect::<String>(),
+              repeat('^').take(width).collect::<String>());
+}
+
+// Report an error from the library and exit.
+fn exit_with_error(e: Error) -> ! {
+    match e {
+        Error::Parse(ref pe) | Error::Eval(ref pe) => print_diagnostic(pe),
+        Error::Config { source: Some(ref source), .. } => eprintln!("error: {}: {}", e, source),
+        _ => eprintln!("error: {}", e),
+    }
+    std::process::exit(1);
+}
+
 fn main() {
     env_logger::init().unwrap();
     let (args, file_args) = parse_options();
@@ -113,7 +200,7 @@ fn main() {
         let output_dir = PathBuf::from(args.output_dir.as_ref().unwrap());
         match traverse_dir(&input_dir, &output_dir)
         {
-            Err(e) => panic!("{}", e),
+            Err(e) => exit_with_error(e),
             _ => ()
         }
     }
@@ -140,7 +227,7 @@ fn main() {
 
         match process(file_path_in_ref, file_path_out_ref, &file_args)
         {
-            Err(e) => panic!("{}", e),
+            Err(e) => exit_with_error(e),
             _ => ()
         }
     }